@@ -0,0 +1,61 @@
+//! Benchmarks of whole programs run by `Interpreter::run`, with their output discarded.
+//!
+//! Run with `cargo bench`, then compare with a saved baseline to spot regressions:
+//! `cargo bench -- --save-baseline before` and `cargo bench -- --baseline before`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fish::{CodeBox, Interpreter};
+
+/// Input of `brainfck.fish`: a brainfuck program printing `ABC`.
+const BRAINFCK_INPUT: &[u8] = b"++++++++[>++++++++<-]>+.+.+.";
+
+/// Builds a stack of 1001 values with `l`, then rotates it 6000 times with `{` and `}`.
+const STACK_ROTATION: &str = "\
+l:aa*a*=?v
+v &*a*aa~<
+>}}}{{{l~&1-:&0=?;";
+
+/// Writes cells with `p` and reads them back with `g` 4900 times, over 80 cells
+/// of which half are inside the codebox.
+const SELF_MODIFICATION: &str = "\
+5e*:*&01.
+>&1-:&:?!;::a%$8%2+p&:&:a%$8%2+g~
+\n\n\n\n";
+
+fn run(code: &CodeBox, input: &[u8]) {
+    let mut interpreter = Interpreter::new(input, std::io::sink());
+    interpreter.set_seed(0);
+    black_box(interpreter.run(black_box(code))).expect("benchmark program failed");
+}
+
+fn bench_programs(c: &mut Criterion) {
+    let programs = [
+        (
+            "pi",
+            CodeBox::load_from_string(include_str!("../examples/pi.fish")),
+            &b""[..],
+        ),
+        (
+            "brainfck",
+            CodeBox::load_from_string(include_str!("../examples/brainfck.fish")),
+            BRAINFCK_INPUT,
+        ),
+        (
+            "stack_rotation",
+            CodeBox::load_from_string(STACK_ROTATION),
+            b"",
+        ),
+        (
+            "self_modification",
+            CodeBox::load_from_string(SELF_MODIFICATION),
+            b"",
+        ),
+    ];
+
+    for (name, code, input) in &programs {
+        c.bench_function(name, |b| b.iter(|| run(code, input)));
+    }
+}
+
+criterion_group!(benches, bench_programs);
+criterion_main!(benches);
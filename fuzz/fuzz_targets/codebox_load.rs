@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(code) = fish::CodeBox::load(data) {
+        let mut interpreter = fish::Interpreter::new(std::io::empty(), std::io::sink());
+        for line in -1..=code.height() as i64 {
+            for chr in -1..=code.width() as i64 {
+                let _ = interpreter.cell_at(&code, chr, line);
+            }
+        }
+        interpreter.advance(&code);
+    }
+});
@@ -0,0 +1,43 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// The first byte selects the options, the rest is the code.
+fuzz_target!(|data: &[u8]| {
+    let (options, code) = match data.split_first() {
+        Some((options, code)) => (*options, code),
+        None => return,
+    };
+    let code = match fish::CodeBox::load(code) {
+        Ok(code) => code,
+        Err(_) => return,
+    };
+
+    let mut interpreter = fish::Interpreter::new(&b"ab\xc3\xa9\n"[..], std::io::sink());
+    interpreter.set_seed(0);
+    interpreter.limits = fish::Limits {
+        max_steps: Some(10_000),
+        max_duration: None,
+        max_stack_size: Some(10_000),
+        max_memory_cells: Some(10_000),
+    };
+    if options & 1 != 0 {
+        interpreter.compat = fish::Compat::FishPy;
+    }
+    if options & 2 != 0 {
+        interpreter.history = Some(fish::History::new(1000));
+    }
+    if options & 4 != 0 {
+        interpreter.overflow = fish::OverflowPolicy::Wrap;
+    }
+    if options & 8 != 0 {
+        interpreter.grow_code = true;
+    }
+
+    if let Err(e) = interpreter.run(&code) {
+        let _ = interpreter.report(e, &code).to_string();
+    }
+    if options & 2 != 0 {
+        while interpreter.step_back() {}
+        let _ = interpreter.run_for(&code, 1000);
+    }
+});
@@ -0,0 +1,246 @@
+use crate::{CodeBox, Direction, InstructionPtr, Result, RuntimeError, Val};
+#[cfg(feature = "bignum")]
+use num_bigint::BigInt;
+#[cfg(feature = "bignum")]
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Which interpreter's semantics to follow where implementations disagree.
+///
+/// `FishPy` reproduces the reference interpreter `fish.py` as closely as possible.
+/// It switches the following behaviors:
+///
+/// - a first line starting with `#!` is not part of the code (see `CodeBox::strip_hashbang`);
+/// - strings given with `Interpreter::push_str` are pushed as Unicode code points;
+/// - each line has its own width: moving right wraps after the last cell of the
+///   current line, moving left from the first column goes to the last cell of the line,
+///   and `.` may jump past the end of a line, reading spaces there. Reading a cell
+///   with `g`, `p` or the instruction pointer makes its line at least that wide;
+/// - cells written with `p` hold any value: zero and negative values are spaces,
+///   values above 255 are invalid instructions;
+/// - popped floats with an integer value are turned into integers, and infinite
+///   floats cannot be popped;
+/// - `=`, `)`, `(` and `?` compare the actual values instead of their truncation
+///   to integers, so `0.5?` does not skip;
+/// - `%` on floats follows Python, `,` by zero is always an error;
+/// - `{` and `}` fail on an empty stack;
+/// - `[` moves as many values as there are when asked for more, and keeps the
+///   first `-x` values on the old stack when `x` is negative;
+/// - `i` reads UTF-8 characters, `o` writes any Unicode character and fails on
+///   values that are not one;
+/// - `n` prints numbers like `NumberFormat::Python`.
+///
+/// Integers only grow without bounds like in Python with the `bignum` feature.
+/// In both modes, `]` on the last stack leaves a single empty stack with no register,
+/// and `i` pushes -1 at the end of the input. The command line also reads the code
+/// as UTF-8 in this mode (see `CodeBox::decode_utf8`) and changes how runs end:
+/// no newline is added to the output, and errors are only reported on the error
+/// output, after a newline if the output did not end with one.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compat {
+    #[default]
+    Native,
+    FishPy,
+}
+
+/// Width of each line as seen by `fish.py`, which grows when cells past
+/// the end of a line are read or written.
+#[derive(Clone, Debug)]
+pub(crate) struct Geometry {
+    /// Widths of the lines having cells, which may be far apart.
+    widths: HashMap<usize, usize>,
+    last_line: usize,
+}
+
+/// Width of a line before an access made it wider, to undo the access.
+#[derive(Clone, Debug)]
+pub(crate) struct Touch {
+    line: usize,
+    width: Option<usize>,
+    last_line: usize,
+}
+
+impl Geometry {
+    pub fn new(code: &CodeBox) -> Self {
+        let widths: HashMap<_, _> = code
+            .data
+            .iter()
+            .map(Vec::len)
+            .enumerate()
+            .filter(|&(_, width)| width > 0)
+            .collect();
+        // empty lines at the end are not part of the code
+        let last_line = widths.keys().max().copied().unwrap_or(0);
+        Geometry { widths, last_line }
+    }
+
+    /// Builds the geometry returned by `widths` and `last_line`.
+    pub fn from_widths(widths: &[(usize, usize)], last_line: usize) -> Self {
+        Geometry {
+            widths: widths.iter().copied().collect(),
+            last_line,
+        }
+    }
+
+    /// Returns the width of each line having cells, sorted by line.
+    pub fn widths(&self) -> Vec<(usize, usize)> {
+        let mut widths: Vec<_> = self.widths.iter().map(|(&y, &w)| (y, w)).collect();
+        widths.sort_unstable();
+        widths
+    }
+
+    pub fn last_line(&self) -> usize {
+        self.last_line
+    }
+
+    fn width(&self, line: usize) -> usize {
+        self.widths.get(&line).copied().unwrap_or(0)
+    }
+
+    /// Records an access to the given cell, returns how to undo it if the geometry changed.
+    pub fn touch(&mut self, x: i64, y: i64) -> Option<Touch> {
+        if x < 0 || y < 0 {
+            return None;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let width = self.widths.get(&y).copied();
+        if width.is_some_and(|w| w > x) {
+            return None;
+        }
+
+        let touch = Touch {
+            line: y,
+            width,
+            last_line: self.last_line,
+        };
+        self.widths.insert(y, x + 1);
+        self.last_line = self.last_line.max(y);
+        Some(touch)
+    }
+
+    /// Restores the geometry as it was before an access.
+    pub fn untouch(&mut self, touch: Touch) {
+        match touch.width {
+            Some(width) => self.widths.insert(touch.line, width),
+            None => self.widths.remove(&touch.line),
+        };
+        self.last_line = touch.last_line;
+    }
+
+    /// Moves the instruction pointer one cell, wrapping like `fish.py`.
+    pub fn advance(&self, ip: &mut InstructionPtr, dir: &Direction) {
+        let last_line = self.last_line;
+        // the instruction pointer cannot reach negative coordinates in this mode
+        let (mut chr, mut line) = (ip.chr as usize, ip.line as usize);
+        match dir {
+            Direction::Down => line = if line >= last_line { 0 } else { line + 1 },
+            Direction::Up => line = line.checked_sub(1).unwrap_or(last_line),
+            _ => {}
+        }
+        // after a jump past the last line
+        if line > last_line {
+            line = 0;
+        }
+
+        let last_chr = self.width(line).saturating_sub(1);
+        match dir {
+            Direction::Right => chr = if chr >= last_chr { 0 } else { chr + 1 },
+            Direction::Left => chr = chr.checked_sub(1).unwrap_or(last_chr),
+            _ => {}
+        }
+
+        ip.chr = chr as i64;
+        ip.line = line as i64;
+    }
+}
+
+/// Returns the instruction stored in a cell written with `p`.
+pub(crate) fn instruction(v: &Val) -> u8 {
+    match v.to_i64() {
+        i if i <= 0 => b' ',
+        i if i <= 255 => i as u8,
+        // not an instruction
+        _ => 0,
+    }
+}
+
+/// Converts a popped value like `fish.py`: floats with an integer value become integers.
+pub(crate) fn popped(v: Val) -> Result<Val> {
+    match v {
+        Val::Float(f) if !f.is_finite() => Err(RuntimeError::IntegerOverflow),
+        Val::Float(f) if f.fract() == 0.0 && f.abs() < 9.2e18 => Ok(Val::Int(f as i64)),
+        #[cfg(feature = "bignum")]
+        Val::Float(f) if f.fract() == 0.0 => Ok(BigInt::from_f64(f).map_or(v, Val::from_big)),
+        v => Ok(v),
+    }
+}
+
+/// Returns `y % x` for floats with the sign of `x`, like Python.
+pub(crate) fn float_rem(y: f64, x: f64) -> f64 {
+    let r = y % x;
+    if r != 0.0 && (r < 0.0) != (x < 0.0) {
+        r + x
+    } else {
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_have_their_own_width() {
+        let code = CodeBox::load_from_string("abc\nd\n\n");
+        let mut g = Geometry::new(&code);
+        let mut ip = InstructionPtr { chr: 0, line: 1 };
+
+        g.advance(&mut ip, &Direction::Right);
+        assert_eq!(ip, InstructionPtr { chr: 0, line: 1 });
+
+        g.advance(&mut ip, &Direction::Down);
+        assert_eq!(ip, InstructionPtr { chr: 0, line: 0 });
+
+        g.advance(&mut ip, &Direction::Left);
+        assert_eq!(ip, InstructionPtr { chr: 2, line: 0 });
+
+        g.touch(4, 1);
+        let mut ip = InstructionPtr { chr: 0, line: 1 };
+        g.advance(&mut ip, &Direction::Left);
+        assert_eq!(ip, InstructionPtr { chr: 4, line: 1 });
+
+        g.touch(0, 3);
+        g.advance(&mut ip, &Direction::Up);
+        assert_eq!(ip, InstructionPtr { chr: 4, line: 0 });
+        g.advance(&mut ip, &Direction::Up);
+        assert_eq!(ip, InstructionPtr { chr: 4, line: 3 });
+
+        g.touch(0, i64::MAX);
+        g.advance(&mut ip, &Direction::Up);
+        assert_eq!(ip, InstructionPtr { chr: 4, line: 2 });
+    }
+
+    #[test]
+    fn untouch_restores_widths() {
+        let code = CodeBox::load_from_string("abc\nd");
+        let mut g = Geometry::new(&code);
+
+        assert!(g.touch(1, 0).is_none());
+        let wider = g.touch(5, 1).unwrap();
+        let below = g.touch(2, 4).unwrap();
+        g.untouch(below);
+        g.untouch(wider);
+
+        assert_eq!(g.widths, Geometry::new(&code).widths);
+        assert_eq!(g.last_line, 1);
+    }
+
+    #[test]
+    fn float_rem_works() {
+        assert_eq!(float_rem(5.5, 2.0), 1.5);
+        assert_eq!(float_rem(-5.5, 2.0), 0.5);
+        assert_eq!(float_rem(5.5, -2.0), -0.5);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::{CodeBox, InstructionPtr, View};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Cells reached by the instruction pointer, accumulated over one or more runs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Coverage {
+    /// Number of executions of each cell, keyed by `(chr, line)`.
+    pub executed: HashMap<(i64, i64), u64>,
+    /// Cells jumped over by `!` or `?`.
+    pub skipped: HashSet<(i64, i64)>,
+    /// Outcomes of each `?` instruction, as `[skipped, not skipped]` counts.
+    pub branches: HashMap<(i64, i64), [u64; 2]>,
+}
+
+/// Coverage state of a single cell.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CellCoverage {
+    /// Blank cell that was never executed.
+    Blank,
+    /// Instruction that was never reached.
+    Missed,
+    /// Instruction that was only jumped over.
+    Skipped,
+    /// Conditional that was executed, with only one of its outcomes taken.
+    Partial,
+    /// Instruction that was executed.
+    Covered,
+}
+
+impl CellCoverage {
+    fn mark(self) -> char {
+        match self {
+            CellCoverage::Blank => ' ',
+            CellCoverage::Missed => '.',
+            CellCoverage::Skipped => '-',
+            CellCoverage::Partial => '%',
+            CellCoverage::Covered => '#',
+        }
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, ip: &InstructionPtr) {
+        *self.executed.entry((ip.chr, ip.line)).or_insert(0) += 1;
+    }
+
+    pub fn record_skip(&mut self, ip: &InstructionPtr) {
+        self.skipped.insert((ip.chr, ip.line));
+    }
+
+    pub fn record_branch(&mut self, ip: &InstructionPtr, skipped: bool) {
+        let outcomes = self.branches.entry((ip.chr, ip.line)).or_insert([0, 0]);
+        outcomes[if skipped { 0 } else { 1 }] += 1;
+    }
+
+    /// Merges the results of another coverage run into this one.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (pos, hits) in &other.executed {
+            *self.executed.entry(*pos).or_insert(0) += hits;
+        }
+        self.skipped.extend(&other.skipped);
+        for (pos, [skipped, taken]) in &other.branches {
+            let outcomes = self.branches.entry(*pos).or_insert([0, 0]);
+            outcomes[0] += skipped;
+            outcomes[1] += taken;
+        }
+    }
+
+    pub fn cell(&self, code: &CodeBox, chr: i64, line: i64) -> CellCoverage {
+        let pos = (chr, line);
+        if self.executed.contains_key(&pos) {
+            match self.branches.get(&pos) {
+                Some([0, _]) | Some([_, 0]) => CellCoverage::Partial,
+                _ => CellCoverage::Covered,
+            }
+        } else if self.skipped.contains(&pos) {
+            CellCoverage::Skipped
+        } else {
+            match instruction(code, chr, line) {
+                Some(_) => CellCoverage::Missed,
+                None => CellCoverage::Blank,
+            }
+        }
+    }
+
+    /// Returns the number of covered and total non-blank cells of the codebox.
+    pub fn summary(&self, code: &CodeBox) -> (usize, usize) {
+        let mut covered = 0;
+        let mut total = 0;
+        for line in 0..code.height as i64 {
+            for chr in 0..code.width as i64 {
+                if instruction(code, chr, line).is_some() {
+                    total += 1;
+                    if self.executed.contains_key(&(chr, line)) {
+                        covered += 1;
+                    }
+                }
+            }
+        }
+        (covered, total)
+    }
+
+    /// Renders the codebox next to a map of coverage marks.
+    ///
+    /// Cells reached outside of the code when it grows are shown too,
+    /// the lines and columns between them and the code are elided with `…`.
+    pub fn annotate(&self, code: &CodeBox) -> String {
+        let mut view = View::new(code.width, code.height);
+        for &(x, y) in self.executed.keys().chain(&self.skipped) {
+            view.include(x, y);
+        }
+        let columns = view.columns();
+
+        let mut out = String::new();
+        for y in view.lines() {
+            let y = match y {
+                Some(y) => y,
+                None => {
+                    out.push_str("…\n");
+                    continue;
+                }
+            };
+            let line: String = columns
+                .iter()
+                .map(|x| match x.map(|x| instruction(code, x, y)) {
+                    Some(Some(c)) if c.is_ascii_graphic() => c as char,
+                    Some(Some(_)) => '?',
+                    Some(None) => ' ',
+                    None => '…',
+                })
+                .collect();
+            let marks: String = columns
+                .iter()
+                .map(|x| match x {
+                    Some(x) => self.cell(code, *x, y).mark(),
+                    None => '…',
+                })
+                .collect();
+            let _ = writeln!(out, "{} | {}", line, marks.trim_end());
+        }
+
+        let (covered, total) = self.summary(code);
+        let _ = writeln!(
+            out,
+            "\n{}/{} cells covered ({:.1}%)",
+            covered,
+            total,
+            100.0 * covered as f64 / total.max(1) as f64
+        );
+        let _ = writeln!(
+            out,
+            "# covered  % partial conditional  - skipped only  . never reached"
+        );
+        out
+    }
+
+    /// Returns the coverage as a JSON document.
+    ///
+    /// Cells outside of the code, reached when it grows, have no instruction.
+    pub fn to_json(&self, code: &CodeBox) -> Value {
+        let mut positions: Vec<(i64, i64)> = self
+            .executed
+            .keys()
+            .chain(&self.skipped)
+            .copied()
+            .filter(|&(chr, line)| !in_code(code, chr, line))
+            .collect();
+        for line in 0..code.height as i64 {
+            for chr in 0..code.width as i64 {
+                if instruction(code, chr, line).is_some() {
+                    positions.push((chr, line));
+                }
+            }
+        }
+        positions.sort_by_key(|&(x, y)| (y, x));
+        positions.dedup();
+
+        let cells: Vec<_> = positions
+            .into_iter()
+            .map(|pos| {
+                let (chr, line) = pos;
+                json!({
+                    "x": chr,
+                    "y": line,
+                    "instruction": instruction(code, chr, line).map(|c| (c as char).to_string()),
+                    "hits": self.executed.get(&pos).copied().unwrap_or(0),
+                    "skipped": self.skipped.contains(&pos),
+                })
+            })
+            .collect();
+
+        let mut branches: Vec<_> = self.branches.iter().collect();
+        branches.sort_by_key(|((x, y), _)| (*y, *x));
+        let branches: Vec<_> = branches
+            .into_iter()
+            .map(|((x, y), [skipped, taken])| {
+                json!({ "x": x, "y": y, "skipped": skipped, "taken": taken })
+            })
+            .collect();
+
+        let (covered, total) = self.summary(code);
+        json!({
+            "width": code.width,
+            "height": code.height,
+            "covered": covered,
+            "total": total,
+            "cells": cells,
+            "branches": branches,
+        })
+    }
+
+    /// Returns the coverage in the lcov tracefile format, one codebox line per source line.
+    ///
+    /// Cells reached outside of the code when it grows are left out,
+    /// lcov having no line numbers for cells above or below it.
+    pub fn to_lcov(&self, source: &str, code: &CodeBox) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "TN:");
+        let _ = writeln!(out, "SF:{}", source);
+
+        let mut lines_found = 0;
+        let mut lines_hit = 0;
+        let source_lines = 0..code.height as i64;
+        for line in source_lines.clone() {
+            let instructions: Vec<i64> = (0..code.width as i64)
+                .filter(|&chr| instruction(code, chr, line).is_some())
+                .collect();
+            if instructions.is_empty() {
+                continue;
+            }
+            let hits = instructions
+                .iter()
+                .filter_map(|&chr| self.executed.get(&(chr, line)))
+                .max()
+                .copied()
+                .unwrap_or(0);
+            lines_found += 1;
+            if hits > 0 {
+                lines_hit += 1;
+            }
+            let _ = writeln!(out, "DA:{},{}", line + 1, hits);
+        }
+
+        let mut branches: Vec<_> = self
+            .branches
+            .iter()
+            .filter(|((_, y), _)| source_lines.contains(y))
+            .collect();
+        branches.sort_by_key(|((x, y), _)| (*y, *x));
+        for ((x, y), outcomes) in &branches {
+            for (i, count) in outcomes.iter().enumerate() {
+                let _ = writeln!(out, "BRDA:{},{},{},{}", y + 1, x, i, count);
+            }
+        }
+        let _ = writeln!(out, "BRF:{}", branches.len() * 2);
+        let _ = writeln!(
+            out,
+            "BRH:{}",
+            branches
+                .iter()
+                .map(|(_, o)| o.iter().filter(|&&c| c > 0).count())
+                .sum::<usize>()
+        );
+
+        let _ = writeln!(out, "LF:{}", lines_found);
+        let _ = writeln!(out, "LH:{}", lines_hit);
+        let _ = writeln!(out, "end_of_record");
+        out
+    }
+}
+
+/// Returns the instruction of the code in a cell, `None` for blank cells
+/// and cells outside of the code.
+fn instruction(code: &CodeBox, chr: i64, line: i64) -> Option<u8> {
+    // negative coordinates become too large
+    match code.get(chr as usize, line as usize) {
+        Some(b' ') | None => None,
+        c => c,
+    }
+}
+
+fn in_code(code: &CodeBox, chr: i64, line: i64) -> bool {
+    code.get(chr as usize, line as usize).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(chr: i64, line: i64) -> InstructionPtr {
+        InstructionPtr { chr, line }
+    }
+
+    #[test]
+    fn cell_coverage_works() {
+        let code = CodeBox::load_from_string("0?1;2");
+        let mut c = Coverage::new();
+        c.record(&ip(0, 0));
+        c.record(&ip(1, 0));
+        c.record_branch(&ip(1, 0), true);
+        c.record_skip(&ip(2, 0));
+        c.record(&ip(3, 0));
+
+        assert_eq!(c.cell(&code, 0, 0), CellCoverage::Covered);
+        assert_eq!(c.cell(&code, 1, 0), CellCoverage::Partial);
+        assert_eq!(c.cell(&code, 2, 0), CellCoverage::Skipped);
+        assert_eq!(c.cell(&code, 4, 0), CellCoverage::Missed);
+        assert_eq!(c.summary(&code), (3, 5));
+    }
+
+    #[test]
+    fn cells_around_the_code_are_reported() {
+        let code = CodeBox::load_from_string("1n;");
+        let mut c = Coverage::new();
+        c.record(&ip(-1, 0));
+        c.record(&ip(0, 0));
+        c.record_skip(&ip(1, i64::MIN));
+
+        assert_eq!(c.cell(&code, -1, 0), CellCoverage::Covered);
+        assert!(c
+            .annotate(&code)
+            .starts_with("     |   -\n…\n 1n; | ##..\n\n"));
+        let cells = &c.to_json(&code)["cells"];
+        assert_eq!(
+            cells[0],
+            json!({"x": 1, "y": i64::MIN, "instruction": null, "hits": 0, "skipped": true})
+        );
+        assert_eq!(
+            cells[1],
+            json!({"x": -1, "y": 0, "instruction": null, "hits": 1, "skipped": false})
+        );
+        assert_eq!(cells.as_array().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn merge_works() {
+        let mut a = Coverage::new();
+        a.record(&ip(0, 0));
+        a.record_branch(&ip(1, 0), true);
+
+        let mut b = Coverage::new();
+        b.record(&ip(0, 0));
+        b.record(&ip(2, 0));
+        b.record_branch(&ip(1, 0), false);
+
+        a.merge(&b);
+
+        assert_eq!(a.executed[&(0, 0)], 2);
+        assert_eq!(a.executed[&(2, 0)], 1);
+        assert_eq!(a.branches[&(1, 0)], [1, 1]);
+    }
+
+    #[test]
+    fn lcov_works() {
+        let code = CodeBox::load_from_string("1n;\n\n2n;");
+        let mut c = Coverage::new();
+        c.record(&ip(0, 0));
+        c.record(&ip(1, 0));
+        c.record(&ip(2, 0));
+
+        assert_eq!(
+            c.to_lcov("test.fish", &code),
+            "TN:\nSF:test.fish\nDA:1,1\nDA:3,0\nBRF:0\nBRH:0\nLF:2\nLH:1\nend_of_record\n"
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use fish::{CodeBox, Direction, Interpreter, StepOutcome, Val, View};
+use std::io::{self, prelude::*};
+
+const HELP: &str = "\
+commands:
+  s, step [N]          execute N instructions (default 1)
+  c, continue          run until a breakpoint is hit, the program ends
+                       or --continue-limit instructions were executed
+  bs, back [N]         undo the last N instructions (default 1)
+  rc, reverse-continue undo instructions until a breakpoint is hit
+  b, break X Y         break when the instruction pointer reaches (X, Y)
+  b, break C           break before executing instruction C
+  d, delete N          delete breakpoint number N
+  bl, breakpoints      list breakpoints
+  w, where             show the current position and surrounding code
+  l, list              show the whole codebox
+  st, stack            show all stacks
+  r, registers         show the register of every stack
+  m, memory            show cells written with p
+  h, help              show this help
+  q, quit              exit the debugger
+an empty line repeats the previous command";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    Cell(i64, i64),
+    Instruction(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Step(u64),
+    Continue,
+    Back(u64),
+    ReverseContinue,
+    Break(Breakpoint),
+    Delete(usize),
+    Breakpoints,
+    Where,
+    List,
+    Stack,
+    Registers,
+    Memory,
+    Help,
+    Quit,
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let cmd = words.next().unwrap_or("");
+    let args: Vec<&str> = words.collect();
+
+    let command = match cmd {
+        "s" | "step" => match args.first() {
+            Some(n) => Command::Step(n.parse().map_err(|_| format!("invalid count: {}", n))?),
+            None => Command::Step(1),
+        },
+        "c" | "continue" => Command::Continue,
+        "bs" | "back" => match args.first() {
+            Some(n) => Command::Back(n.parse().map_err(|_| format!("invalid count: {}", n))?),
+            None => Command::Back(1),
+        },
+        "rc" | "reverse-continue" => Command::ReverseContinue,
+        "b" | "break" => Command::Break(parse_breakpoint(&args)?),
+        "d" | "delete" => match args.first() {
+            Some(n) => Command::Delete(
+                n.parse()
+                    .map_err(|_| format!("invalid breakpoint number: {}", n))?,
+            ),
+            None => return Err("missing breakpoint number".to_string()),
+        },
+        "bl" | "breakpoints" => Command::Breakpoints,
+        "w" | "where" => Command::Where,
+        "l" | "list" => Command::List,
+        "st" | "stack" => Command::Stack,
+        "r" | "registers" => Command::Registers,
+        "m" | "memory" => Command::Memory,
+        "h" | "help" => Command::Help,
+        "q" | "quit" => Command::Quit,
+        _ => return Err(format!("unknown command: {}", cmd)),
+    };
+
+    Ok(command)
+}
+
+fn parse_breakpoint(args: &[&str]) -> Result<Breakpoint, String> {
+    // accept both "X Y" and "X,Y"
+    let coords: Vec<&str> = args
+        .iter()
+        .flat_map(|a| a.split(','))
+        .filter(|a| !a.is_empty())
+        .collect();
+
+    match coords.as_slice() {
+        [x, y] => {
+            let x = x.parse().map_err(|_| format!("invalid position: {}", x))?;
+            let y = y.parse().map_err(|_| format!("invalid position: {}", y))?;
+            Ok(Breakpoint::Cell(x, y))
+        }
+        _ => match args {
+            [c] if c.len() == 1 => Ok(Breakpoint::Instruction(c.as_bytes()[0])),
+            _ => Err("expected a position (X Y) or a single instruction".to_string()),
+        },
+    }
+}
+
+fn format_instruction(c: u8) -> String {
+    if c.is_ascii_graphic() || c == b' ' {
+        format!("'{}'", c as char)
+    } else {
+        format!("0x{:02x}", c)
+    }
+}
+
+fn dir_name(dir: &Direction) -> &'static str {
+    match dir {
+        Direction::Right => "right",
+        Direction::Left => "left",
+        Direction::Up => "up",
+        Direction::Down => "down",
+    }
+}
+
+pub struct Debugger<'a, R: Read, W: Write> {
+    fish: Interpreter<R, W>,
+    code: &'a CodeBox,
+    breakpoints: Vec<Breakpoint>,
+    running: bool,
+    continue_limit: u64,
+}
+
+impl<'a, R: Read, W: Write> Debugger<'a, R, W> {
+    /// Creates a debugger where continue returns to the prompt after at most
+    /// `continue_limit` instructions.
+    pub fn new(fish: Interpreter<R, W>, code: &'a CodeBox, continue_limit: u64) -> Self {
+        Debugger {
+            fish,
+            code,
+            breakpoints: vec![],
+            running: true,
+            continue_limit,
+        }
+    }
+
+    /// Reads commands from the standard input until the user quits.
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut last = Command::Step(1);
+
+        println!("fishr debugger, type 'help' for a list of commands");
+        self.show_where();
+
+        loop {
+            print!("(fishr) ");
+            io::stdout().flush().expect("flush failed");
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            let command = if line.trim().is_empty() {
+                last.clone()
+            } else {
+                match parse_command(&line) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                }
+            };
+
+            if command == Command::Quit {
+                break;
+            }
+
+            self.handle(&command);
+            last = command;
+        }
+    }
+
+    fn handle(&mut self, command: &Command) {
+        match command {
+            Command::Step(n) => {
+                for _ in 0..*n {
+                    if !self.step() {
+                        break;
+                    }
+                }
+                if self.running {
+                    self.show_where();
+                }
+            }
+            Command::Continue => {
+                let mut steps = 0;
+                while self.step() {
+                    if let Some(i) = self.hit_breakpoint() {
+                        println!("breakpoint {} hit", i);
+                        self.show_where();
+                        break;
+                    }
+                    steps += 1;
+                    if steps == self.continue_limit {
+                        println!("stopped after {} steps without hitting a breakpoint", steps);
+                        self.show_where();
+                        break;
+                    }
+                }
+            }
+            Command::Back(n) => {
+                for _ in 0..*n {
+                    if !self.step_back() {
+                        break;
+                    }
+                }
+                self.show_where();
+            }
+            Command::ReverseContinue => {
+                while self.step_back() {
+                    if let Some(i) = self.hit_breakpoint() {
+                        println!("breakpoint {} hit", i);
+                        break;
+                    }
+                }
+                self.show_where();
+            }
+            Command::Break(bp) => {
+                self.breakpoints.push(bp.clone());
+                println!("breakpoint {} set", self.breakpoints.len() - 1);
+            }
+            Command::Delete(i) => {
+                if *i < self.breakpoints.len() {
+                    self.breakpoints.remove(*i);
+                } else {
+                    println!("no breakpoint number {}", i);
+                }
+            }
+            Command::Breakpoints => {
+                for (i, bp) in self.breakpoints.iter().enumerate() {
+                    match bp {
+                        Breakpoint::Cell(x, y) => println!("{}: at ({}, {})", i, x, y),
+                        Breakpoint::Instruction(c) => {
+                            println!("{}: on {}", i, format_instruction(*c))
+                        }
+                    }
+                }
+            }
+            Command::Where => self.show_where(),
+            Command::List => self.show_code(&self.view().lines()),
+            Command::Stack => self.show_stacks(),
+            Command::Registers => self.show_registers(),
+            Command::Memory => self.show_memory(),
+            Command::Help => println!("{}", HELP),
+            Command::Quit => {}
+        }
+    }
+
+    /// Executes a single instruction, returns false if the program is no longer running.
+    fn step(&mut self) -> bool {
+        if !self.running {
+            println!("the program is not running");
+            return false;
+        }
+
+        match self.fish.step(self.code) {
+            Ok(StepOutcome::Stopped { .. }) => {
+                println!();
+                println!("program finished after {} steps", self.fish.steps);
+                self.running = false;
+                false
+            }
+            Ok(_) => true,
+            Err(e) => {
+                println!();
+                println!("{}", self.fish.report(e, self.code));
+                self.running = false;
+                false
+            }
+        }
+    }
+
+    /// Undoes a single instruction, returns false if there is nothing left to undo.
+    fn step_back(&mut self) -> bool {
+        if !self.fish.step_back() {
+            println!("no more history");
+            return false;
+        }
+        self.running = true;
+        true
+    }
+
+    fn hit_breakpoint(&self) -> Option<usize> {
+        let instruction = self.fish.fetch(self.code);
+        self.breakpoints.iter().position(|bp| match bp {
+            Breakpoint::Cell(x, y) => self.fish.ip.chr == *x && self.fish.ip.line == *y,
+            Breakpoint::Instruction(c) => instruction == Some(*c),
+        })
+    }
+
+    fn show_where(&self) {
+        let ip = &self.fish.ip;
+        let next = self
+            .fish
+            .fetch(self.code)
+            .map_or("none".to_string(), format_instruction);
+        println!(
+            "step {}: at ({}, {}) moving {}, next instruction {}",
+            self.fish.steps,
+            ip.chr,
+            ip.line,
+            dir_name(&self.fish.dir),
+            next
+        );
+
+        let lines = self.view().lines();
+        let near = |y: &Option<i64>| y.is_some_and(|y| y.abs_diff(ip.line) <= 2);
+        let first = lines.iter().position(near).unwrap_or(0);
+        let last = lines.iter().rposition(near).unwrap_or(0);
+        self.show_code(&lines[first..=last]);
+    }
+
+    /// Returns the lines and columns to show: those of the code, of the cells
+    /// written around it and of the instruction pointer.
+    fn view(&self) -> View {
+        let mut view = self.fish.view(self.code);
+        view.include(self.fish.ip.chr, self.fish.ip.line);
+        view
+    }
+
+    /// Shows the given lines of the codebox, `None` standing for elided lines.
+    fn show_code(&self, lines: &[Option<i64>]) {
+        let columns = self.view().columns();
+        let ip = &self.fish.ip;
+        for y in lines {
+            let y = match *y {
+                Some(y) => y,
+                None => {
+                    println!("{:>4} |", "…");
+                    continue;
+                }
+            };
+            let text: String = columns
+                .iter()
+                .map(|x| match x.map(|x| self.fish.cell_at(self.code, x, y)) {
+                    Some(Some(c)) if c.is_ascii_graphic() || c == b' ' => c as char,
+                    Some(Some(_)) => '?',
+                    Some(None) => ' ',
+                    None => '…',
+                })
+                .collect();
+            println!("{:>4} | {}", y, text.trim_end());
+            if y == ip.line {
+                let offset = columns.iter().position(|&x| x == Some(ip.chr)).unwrap_or(0);
+                println!("     | {:>w$}^", "", w = offset);
+            }
+        }
+    }
+
+    fn show_stacks(&self) {
+        let stacks = std::iter::once(&self.fish.stack.initial_stack)
+            .chain(&self.fish.stack.additional_stacks);
+        for (i, stack) in stacks.enumerate() {
+            let values: Vec<String> = stack.values.iter().map(Val::to_string).collect();
+            println!("{}: [{}]", i, values.join(", "));
+        }
+    }
+
+    fn show_registers(&self) {
+        let stacks = std::iter::once(&self.fish.stack.initial_stack)
+            .chain(&self.fish.stack.additional_stacks);
+        for (i, stack) in stacks.enumerate() {
+            match &stack.register {
+                Some(v) => println!("{}: {}", i, v),
+                None => println!("{}: empty", i),
+            }
+        }
+    }
+
+    fn show_memory(&self) {
+        let cells = self.fish.written_cells();
+        if cells.is_empty() {
+            println!("no cells written");
+        }
+        for (pos, v) in cells {
+            println!("({}, {}) = {}", pos.x, pos.y, v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_step_works() {
+        assert_eq!(parse_command("s"), Ok(Command::Step(1)));
+        assert_eq!(parse_command("step 10"), Ok(Command::Step(10)));
+        assert!(parse_command("step x").is_err());
+    }
+
+    #[test]
+    fn parse_back_works() {
+        assert_eq!(parse_command("bs"), Ok(Command::Back(1)));
+        assert_eq!(parse_command("back 5"), Ok(Command::Back(5)));
+        assert_eq!(parse_command("rc"), Ok(Command::ReverseContinue));
+    }
+
+    #[test]
+    fn parse_cell_breakpoint_works() {
+        assert_eq!(
+            parse_command("b 3 4"),
+            Ok(Command::Break(Breakpoint::Cell(3, 4)))
+        );
+        assert_eq!(
+            parse_command("break 3,4"),
+            Ok(Command::Break(Breakpoint::Cell(3, 4)))
+        );
+    }
+
+    #[test]
+    fn parse_instruction_breakpoint_works() {
+        assert_eq!(
+            parse_command("b n"),
+            Ok(Command::Break(Breakpoint::Instruction(b'n')))
+        );
+        assert_eq!(
+            parse_command("b 5"),
+            Ok(Command::Break(Breakpoint::Instruction(b'5')))
+        );
+    }
+
+    #[test]
+    fn parse_unknown_command_fails() {
+        assert!(parse_command("frobnicate").is_err());
+    }
+}
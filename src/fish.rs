@@ -1,25 +1,68 @@
+mod compat;
+mod coverage;
+mod format;
+mod grid;
+mod history;
+mod limits;
+mod op;
+mod profile;
+mod replay;
+mod report;
+mod snapshot;
 mod stack;
+mod trace;
 mod val;
 
+pub use crate::compat::Compat;
+use crate::compat::{Geometry, Touch};
+pub use crate::coverage::{CellCoverage, Coverage};
+pub use crate::format::{python_float, NumberFormat};
+use crate::grid::Grid;
+pub use crate::grid::{Bounds, View};
+pub use crate::history::History;
+use crate::history::{Delta, MemoryDelta, StackDelta};
+pub use crate::limits::Limits;
+use crate::op::Op;
+pub use crate::profile::Profile;
+pub use crate::replay::{Event, Recording, Replay};
+pub use crate::report::{ErrorReport, ExcerptLine};
+pub use crate::snapshot::Snapshot;
 pub use crate::stack::{Stack, StackOfStacks};
-pub use crate::val::Val;
+pub use crate::trace::{diff_traces, Divergence};
+pub use crate::val::{OverflowPolicy, Val};
 use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, to_value, Value};
 use std::{
     cmp,
     collections::HashMap,
+    convert::TryFrom,
+    error, fmt,
     fs::File,
     io,
     io::{prelude::*, stderr, BufReader, Bytes, Cursor},
+    mem,
     path::Path,
-    result, thread,
-    time::Duration,
+    result,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
 };
 
+/// Identifier of the next codebox, see `CodeBox::id`.
+static NEXT_CODE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Program code, a grid of cells holding bytes, or Unicode characters once decoded
+/// with `decode_utf8`.
 pub struct CodeBox {
-    data: Vec<Vec<u8>>,
+    data: Vec<Vec<char>>,
     height: usize,
     width: usize,
+    unicode: bool,
+    /// Unique to each codebox and changed along with its cells, to tell when
+    /// the decoded code of the interpreter is out of date.
+    id: u64,
 }
 
 impl CodeBox {
@@ -28,9 +71,11 @@ impl CodeBox {
             data: vec![],
             width: 0,
             height: 0,
+            unicode: false,
+            id: Self::next_id(),
         };
         for line in BufReader::new(r).lines() {
-            code_box.push(line?.as_bytes().to_vec());
+            code_box.push(line?.bytes().map(char::from).collect());
         }
         Ok(code_box)
     }
@@ -53,16 +98,98 @@ impl CodeBox {
         self.height
     }
 
-    fn push(&mut self, line: Vec<u8>) {
+    /// Returns whether cells hold Unicode characters instead of bytes.
+    pub fn is_unicode(&self) -> bool {
+        self.unicode
+    }
+
+    /// Removes the first line if it starts with `#!`, as `fish.py` does.
+    pub fn strip_hashbang(&mut self) {
+        if self
+            .data
+            .first()
+            .is_some_and(|line| line.starts_with(&['#', '!']))
+        {
+            self.data.remove(0);
+            self.height -= 1;
+            self.width = self.data.iter().map(Vec::len).max().unwrap_or(0);
+            self.id = Self::next_id();
+        }
+    }
+
+    /// Decodes the code as UTF-8, so that each cell holds a Unicode character
+    /// instead of a byte. Lines become as wide as their number of characters.
+    pub fn decode_utf8(&mut self) {
+        if self.unicode {
+            return;
+        }
+        for line in &mut self.data {
+            *line = String::from_utf8_lossy(&Self::bytes(line))
+                .chars()
+                .collect();
+        }
+        self.width = self.data.iter().map(Vec::len).max().unwrap_or(0);
+        self.unicode = true;
+        self.id = Self::next_id();
+    }
+
+    /// Returns the cells of each line, as bytes or as code points when
+    /// the code is Unicode.
+    pub(crate) fn cells(&self) -> Vec<Vec<u32>> {
+        self.data
+            .iter()
+            .map(|line| line.iter().map(|&c| c as u32).collect())
+            .collect()
+    }
+
+    /// Builds a codebox from the cells returned by `cells`.
+    pub(crate) fn from_cells(cells: &[Vec<u32>], unicode: bool) -> CodeBox {
+        let mut code_box = CodeBox {
+            data: vec![],
+            width: 0,
+            height: 0,
+            unicode,
+            id: Self::next_id(),
+        };
+        for line in cells {
+            code_box.push(
+                line.iter()
+                    .map(|&c| char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect(),
+            );
+        }
+        code_box
+    }
+
+    fn next_id() -> u64 {
+        NEXT_CODE_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn bytes(line: &[char]) -> Vec<u8> {
+        line.iter().map(|&c| c as u8).collect()
+    }
+
+    fn push(&mut self, line: Vec<char>) {
+        self.id = Self::next_id();
         self.height += 1;
         self.width = cmp::max(line.len(), self.width);
         self.data.push(line);
     }
 
+    /// Returns the instruction in a cell.
     fn get(&self, x: usize, y: usize) -> Option<u8> {
+        self.get_char(x, y).map(Self::instruction)
+    }
+
+    /// Returns the instruction a character stands for, characters above 255 are not instructions.
+    fn instruction(c: char) -> u8 {
+        u8::try_from(c).unwrap_or_default()
+    }
+
+    fn get_char(&self, x: usize, y: usize) -> Option<char> {
         if x < self.width && y < self.height {
             let line = self.data.get(y)?;
-            Some(line.get(x).map_or(b' ', |c| *c))
+            Some(line.get(x).map_or(' ', |c| *c))
         } else {
             None
         }
@@ -73,17 +200,19 @@ impl CodeBox {
         if let Some(line) = self.data.get_mut(y) {
             if x < self.width {
                 if x + 1 > line.len() {
-                    line.resize(x + 1, b' ');
+                    line.resize(x + 1, ' ');
                 }
                 if let Some(p) = line.get_mut(x) {
-                    *p = val;
+                    *p = char::from(val);
                 }
+                self.id = Self::next_id();
             }
         }
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Direction {
     Right,
     Left,
@@ -91,9 +220,14 @@ pub enum Direction {
     Down,
 }
 
+/// Position of the instruction pointer.
+///
+/// Coordinates are signed since code can grow past its top and left edges
+/// (see `Interpreter::grow_code`).
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct InstructionPtr {
-    pub chr: usize,
-    pub line: usize,
+    pub chr: i64,
+    pub line: i64,
 }
 
 pub enum RuntimeStatus {
@@ -101,7 +235,56 @@ pub enum RuntimeStatus {
     Stop,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+/// What the instruction pointer does after an instruction, as seen by `step`.
+enum Flow {
+    Continue,
+    /// The following cell is skipped.
+    Skip,
+    Stop,
+}
+
+/// Outcome of a single call to `Interpreter::step`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepOutcome {
+    /// The instruction was executed and the instruction pointer moved to the next cell.
+    Continued { instruction: u8, ip: InstructionPtr },
+    /// The instruction was executed and the following cell was skipped.
+    Skipped { instruction: u8, ip: InstructionPtr },
+    /// The instruction ended the program.
+    Stopped { instruction: u8, ip: InstructionPtr },
+}
+
+impl StepOutcome {
+    /// Returns the executed instruction.
+    pub fn instruction(&self) -> u8 {
+        match *self {
+            StepOutcome::Continued { instruction, .. }
+            | StepOutcome::Skipped { instruction, .. }
+            | StepOutcome::Stopped { instruction, .. } => instruction,
+        }
+    }
+
+    /// Returns the position of the executed instruction.
+    pub fn ip(&self) -> &InstructionPtr {
+        match self {
+            StepOutcome::Continued { ip, .. }
+            | StepOutcome::Skipped { ip, .. }
+            | StepOutcome::Stopped { ip, .. } => ip,
+        }
+    }
+}
+
+/// State of the interpreter after a call to `Interpreter::run_for`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum RunState {
+    /// The program ended.
+    Finished,
+    /// The step budget was exhausted before the program ended,
+    /// execution can be resumed with another call to `run_for`.
+    Suspended,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub enum RuntimeError {
     InvalidInstruction,
     InvalidIpPosition,
@@ -109,52 +292,191 @@ pub enum RuntimeError {
     IntegerOverflow,
     DivideByZero,
     IOError,
+    StepLimitExceeded,
+    TimeLimitExceeded,
+    StackLimitExceeded,
+    MemoryLimitExceeded,
+    ReplayDiverged,
+    InvalidCharacter,
+    NonFiniteFloat,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            RuntimeError::InvalidInstruction => "invalid instruction",
+            RuntimeError::InvalidIpPosition => "instruction pointer outside of the codebox",
+            RuntimeError::StackUnderflow => "stack underflow",
+            RuntimeError::IntegerOverflow => "integer overflow",
+            RuntimeError::DivideByZero => "division by zero",
+            RuntimeError::IOError => "I/O error",
+            RuntimeError::StepLimitExceeded => "step limit exceeded",
+            RuntimeError::TimeLimitExceeded => "time limit exceeded",
+            RuntimeError::StackLimitExceeded => "stack size limit exceeded",
+            RuntimeError::MemoryLimitExceeded => "memory limit exceeded",
+            RuntimeError::ReplayDiverged => "run diverged from the recording",
+            RuntimeError::InvalidCharacter => "value is not a valid character",
+            RuntimeError::NonFiniteFloat => "float is infinite or NaN",
+        };
+        f.write_str(msg)
+    }
 }
 
+impl error::Error for RuntimeError {}
+
 pub type Result<T> = result::Result<T, RuntimeError>;
 
-enum ParserState {
+/// Whether the instruction pointer is inside a string literal.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParserState {
     Normal,
     SingleQuoted,
     DoubleQuoted,
 }
 
-#[derive(Hash, Eq, PartialEq, Debug)]
+#[derive(Clone, Hash, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct MemPos {
     pub x: i64,
     pub y: i64,
 }
 
+/// Random number generator used by `x`.
+enum Random {
+    /// Seeded generator, whose state can be saved in a snapshot.
+    Seeded(Box<ChaCha12Rng>),
+    Custom(Box<dyn RngCore>),
+}
+
+impl RngCore for Random {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Random::Seeded(rng) => rng.next_u32(),
+            Random::Custom(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Random::Seeded(rng) => rng.next_u64(),
+            Random::Custom(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Random::Seeded(rng) => rng.fill_bytes(dest),
+            Random::Custom(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> result::Result<(), rand::Error> {
+        match self {
+            Random::Seeded(rng) => rng.try_fill_bytes(dest),
+            Random::Custom(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
 pub struct Interpreter<R: Read, W: Write> {
     pub ip: InstructionPtr,
     pub dir: Direction,
     pub stack: StackOfStacks<Val>,
+    /// Cells written with `p` outside of the codebox. Cells of the codebox are
+    /// written in place once it runs, see `written_cells`.
     pub memory: HashMap<MemPos, Val>,
+    pub steps: u64,
+    /// Behavior of `+`, `-` and `*` when an integer result does not fit in an `i64`.
+    pub overflow: OverflowPolicy,
+    /// Make `,` produce exact fractions instead of floats, when dividing integers or fractions.
+    #[cfg(feature = "rational")]
+    pub exact_division: bool,
+    /// How `n` prints numbers.
+    pub number_format: NumberFormat,
+    /// Semantics to follow where interpreters disagree.
+    pub compat: Compat,
+    /// Read and write UTF-8 characters with `i` and `o`, and push strings as
+    /// Unicode code points, instead of bytes.
+    pub unicode: bool,
+    /// Grow the codebox to include the cells written with `p` around it, so that
+    /// the instruction pointer can reach them, at negative coordinates too. It
+    /// wraps around the smallest rectangle holding the code and these cells.
+    /// `Compat::FishPy` has its own way of growing lines, used instead.
+    pub grow_code: bool,
 
     pub trace: bool,
     pub tick: Option<Duration>,
-
-    input: Bytes<R>,
+    /// Also tick on whitespace and skipped cells, instead of executed instructions only.
+    pub always_tick: bool,
+    pub limits: Limits,
+    /// Execution counts, collected when set to `Some`.
+    pub profile: Option<Profile>,
+    /// Executed and skipped cells, collected when set to `Some`.
+    pub coverage: Option<Coverage>,
+    /// Nondeterministic events of the run, recorded when set to `Some`.
+    pub recording: Option<Recording>,
+    /// Recorded events to use instead of the input and random number generator.
+    pub replay: Option<Replay>,
+    /// Undo information for the last steps, kept when set to `Some`.
+    pub history: Option<History>,
+
+    started: Option<Instant>,
+    input: Bytes<BufReader<R>>,
     output: W,
-    rng: ThreadRng,
+    rng: Random,
+    seed: Option<u64>,
     state: ParserState,
-    memory_is_dirty: bool,
+    /// Decoded code being run, with the cells written inside of it.
+    grid: Option<Grid>,
+    consumed: Vec<Event>,
+    touched: Vec<Touch>,
+    /// Operands popped by the instruction being executed, put back if it fails.
+    popped: Vec<Val>,
+    geometry: Option<Geometry>,
+    newline: Option<bool>,
 }
 
 impl<R: Read, W: Write> Interpreter<R, W> {
     pub fn new(input: R, output: W) -> Interpreter<R, W> {
+        Self::with_rng(input, output, thread_rng())
+    }
+
+    /// Creates an interpreter using the given random number generator for `x`.
+    pub fn with_rng<G: RngCore + 'static>(input: R, output: W, rng: G) -> Interpreter<R, W> {
         Interpreter {
             ip: InstructionPtr { chr: 0, line: 0 },
             dir: Direction::Right,
             stack: StackOfStacks::new(),
             memory: HashMap::new(),
+            steps: 0,
+            overflow: OverflowPolicy::default(),
+            #[cfg(feature = "rational")]
+            exact_division: false,
+            number_format: NumberFormat::default(),
+            compat: Compat::default(),
+            unicode: false,
+            grow_code: false,
             trace: false,
             tick: None,
-            input: input.bytes(),
+            always_tick: false,
+            limits: Limits::default(),
+            profile: None,
+            coverage: None,
+            recording: None,
+            replay: None,
+            history: None,
+            started: None,
+            input: BufReader::new(input).bytes(),
             output,
-            rng: thread_rng(),
+            rng: Random::Custom(Box::new(rng)),
+            seed: None,
             state: ParserState::Normal,
-            memory_is_dirty: false,
+            grid: None,
+            consumed: vec![],
+            touched: vec![],
+            popped: vec![],
+            geometry: None,
+            newline: None,
         }
     }
 
@@ -162,6 +484,142 @@ impl<R: Read, W: Write> Interpreter<R, W> {
         self.ip = InstructionPtr { chr: 0, line: 0 };
         self.dir = Direction::Right;
         self.state = ParserState::Normal;
+        self.steps = 0;
+        self.started = None;
+        self.geometry = None;
+        self.newline = None;
+        self.release_grid();
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    /// Replaces the random number generator with a deterministic one
+    /// initialized from `seed`, so that runs using `x` can be reproduced.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Random::Seeded(Box::new(ChaCha12Rng::seed_from_u64(seed)));
+        self.seed = Some(seed);
+    }
+
+    /// Returns the seed set with `set_seed`, if any.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Returns whether the last output of `o` or `n` ended with a newline,
+    /// `None` if nothing was written since the interpreter was reset.
+    pub fn output_ends_with_newline(&self) -> Option<bool> {
+        self.newline
+    }
+
+    /// Returns the cells written with `p`, sorted by position.
+    pub fn written_cells(&self) -> Vec<(MemPos, Val)> {
+        let in_code = self.grid.iter().flat_map(Grid::written_cells);
+        let mut cells: Vec<_> = self
+            .memory
+            .iter()
+            .map(|(pos, v)| (pos.clone(), v))
+            .chain(in_code)
+            .map(|(pos, v)| (pos, v.clone()))
+            .collect();
+        cells.sort_by_key(|(pos, _)| (pos.y, pos.x));
+        cells
+    }
+
+    /// Captures the complete state of the interpreter running the given code.
+    pub fn snapshot(&self, code: &CodeBox) -> Snapshot {
+        let memory = self.written_cells();
+
+        Snapshot {
+            code: code.cells(),
+            unicode: code.is_unicode(),
+            ip: self.ip.clone(),
+            dir: self.dir.clone(),
+            state: self.state.clone(),
+            stack: self.stack.clone(),
+            memory,
+            steps: self.steps,
+            seed: self.seed,
+            rng: match &self.rng {
+                Random::Seeded(rng) => Some(*rng.clone()),
+                Random::Custom(_) => None,
+            },
+            newline: self.newline,
+            line_widths: self.geometry.as_ref().map(Geometry::widths),
+            last_line: self.geometry.as_ref().map_or(0, Geometry::last_line),
+            overflow: self.overflow,
+            #[cfg(feature = "rational")]
+            exact_division: self.exact_division,
+            #[cfg(not(feature = "rational"))]
+            exact_division: false,
+            number_format: self.number_format,
+            compat: self.compat,
+            grow_code: self.grow_code,
+        }
+    }
+
+    /// Restores a state captured with `snapshot`, execution can then continue with `resume`.
+    ///
+    /// The options saved along with the state are restored too. The random number
+    /// generator is only replaced if its state was saved.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.ip = snapshot.ip.clone();
+        self.dir = snapshot.dir.clone();
+        self.state = snapshot.state.clone();
+        self.stack = snapshot.stack.clone();
+        self.memory = snapshot.memory.iter().cloned().collect();
+        self.grid = None;
+        self.steps = snapshot.steps;
+        self.started = None;
+        self.newline = snapshot.newline;
+        self.geometry = snapshot
+            .line_widths
+            .as_ref()
+            .map(|widths| Geometry::from_widths(widths, snapshot.last_line));
+        self.overflow = snapshot.overflow;
+        #[cfg(feature = "rational")]
+        {
+            self.exact_division = snapshot.exact_division;
+        }
+        self.number_format = snapshot.number_format;
+        self.compat = snapshot.compat;
+        self.grow_code = snapshot.grow_code;
+        if let Some(rng) = &snapshot.rng {
+            self.rng = Random::Seeded(Box::new(rng.clone()));
+            self.seed = snapshot.seed;
+        }
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    /// Builds a detailed report for an error returned by `run` or `execute`,
+    /// capturing the current position, direction and top stack. A failing
+    /// instruction leaves its operands on the stack.
+    pub fn report(&self, error: RuntimeError, code: &CodeBox) -> ErrorReport {
+        let (lines, chrs) = report::excerpt_window(&self.ip, &self.bounds(code));
+        let excerpt = lines
+            .map(|line| ExcerptLine {
+                line,
+                first_chr: *chrs.start(),
+                cells: chrs
+                    .clone()
+                    .map(|chr| self.cell_at(code, chr, line).unwrap_or(b' '))
+                    .collect(),
+            })
+            .collect();
+
+        let top_stack = self.stack.top();
+        ErrorReport {
+            error,
+            ip: self.ip.clone(),
+            dir: self.dir.clone(),
+            instruction: self.fetch(code),
+            steps: self.steps,
+            stack: top_stack.values.iter().cloned().collect(),
+            register: top_stack.register.clone(),
+            excerpt,
+        }
     }
 
     pub fn dump_state(&self, instruction: u8) {
@@ -186,21 +644,35 @@ impl<R: Read, W: Write> Interpreter<R, W> {
                 Val::Byte(val) => to_value(val),
                 Val::Int(val) => to_value(val),
                 Val::Float(val) => to_value(val),
+                #[cfg(feature = "bignum")]
+                Val::Big(val) => to_value(val.to_string()),
+                #[cfg(feature = "rational")]
+                Val::Ratio(_) => to_value(val.to_f64()),
             }.unwrap_or(Value::Null)).collect::<Vec<_>>(),
 
             "register": top_stack.register.as_ref().map(|val| match val {
                 Val::Byte(val) => to_value(val),
                 Val::Int(val) => to_value(val),
                 Val::Float(val) => to_value(val),
+                #[cfg(feature = "bignum")]
+                Val::Big(val) => to_value(val.to_string()),
+                #[cfg(feature = "rational")]
+                Val::Ratio(_) => to_value(val.to_f64()),
             }.unwrap_or(Value::Null)),
         });
 
-        writeln!(&mut stderr(), "{}", state.to_string()).expect("writeln! failed");
+        writeln!(&mut stderr(), "{}", state).expect("writeln! failed");
     }
 
     pub fn push_str(&mut self, s: &str) {
-        for c in s.bytes() {
-            self.stack.top_mut().push(Val::Byte(c as u8));
+        if self.unicode_io() {
+            for c in s.chars() {
+                self.stack.top_mut().push(Val::from(c));
+            }
+        } else {
+            for c in s.bytes() {
+                self.stack.top_mut().push(Val::Byte(c));
+            }
         }
     }
 
@@ -210,93 +682,546 @@ impl<R: Read, W: Write> Interpreter<R, W> {
 
     pub fn run(&mut self, code: &CodeBox) -> Result<()> {
         self.reset();
+
+        if self.trace {
+            if let Some(seed) = self.seed {
+                writeln!(&mut stderr(), "{}", json!({ "seed": seed })).expect("writeln! failed");
+            }
+        }
+
+        self.resume(code)
+    }
+
+    /// Runs the program until it ends, starting from the current position
+    /// without resetting the interpreter.
+    pub fn resume(&mut self, code: &CodeBox) -> Result<()> {
         loop {
-            let instruction = match self.fetch(code) {
-                Some(ch) => ch,
+            if let StepOutcome::Stopped { .. } = self.step(code)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes at most `n_steps` instructions without resetting the interpreter,
+    /// starting from the current position.
+    pub fn run_for(&mut self, code: &CodeBox, n_steps: u64) -> Result<RunState> {
+        for _ in 0..n_steps {
+            if let StepOutcome::Stopped { .. } = self.step(code)? {
+                return Ok(RunState::Finished);
+            }
+        }
+        Ok(RunState::Suspended)
+    }
+
+    /// Fetches and executes the instruction under the instruction pointer,
+    /// then moves to the next cell unless the program ended.
+    ///
+    /// On error, the instruction pointer is left on the failing cell.
+    /// The code may differ from one call to the next: the cells written
+    /// with `p` then apply to the new code.
+    pub fn step(&mut self, code: &CodeBox) -> Result<StepOutcome> {
+        if !self.limits.is_unlimited() {
+            self.check_limits()?;
+        }
+
+        self.load_grid(code);
+        let (chr, line) = (self.ip.chr, self.ip.line);
+        // decoded from this code just above
+        let op = match self.grid.as_ref().and_then(|grid| grid.op(chr, line)) {
+            Some(op) => op,
+            // outside of the code
+            None => match self.fetch(code) {
+                Some(ch) => Op::decode(ch),
                 None => return Err(RuntimeError::InvalidIpPosition),
-            };
+            },
+        };
+        let instruction = op.byte();
+        if self.compat == Compat::FishPy {
+            let (x, y) = (self.ip.chr, self.ip.line);
+            self.touch(code, x, y);
+        }
+
+        if self.trace {
+            self.dump_state(instruction);
+        }
+
+        let ip = self.ip.clone();
+        // characters pushed in string mode, closing quotes are instructions
+        let quoted = match self.state {
+            ParserState::Normal => false,
+            ParserState::SingleQuoted => instruction != b'\'',
+            ParserState::DoubleQuoted => instruction != b'"',
+        };
+        let status = match self.history {
+            Some(_) => self.execute_with_history(op, code)?,
+            None => self.execute_op(op, code)?,
+        };
+        self.steps += 1;
+
+        if let Some(profile) = &mut self.profile {
+            profile.record(&ip, instruction, quoted);
+        }
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(&ip);
+            if instruction == b'?' && !quoted {
+                coverage.record_branch(&ip, matches!(status, Flow::Skip));
+            }
+        }
+
+        if let Some(max) = self.limits.max_stack_size {
+            if self.stack.total_len() > max {
+                return Err(RuntimeError::StackLimitExceeded);
+            }
+        }
+
+        if let Flow::Stop = status {
+            return Ok(StepOutcome::Stopped { instruction, ip });
+        }
+
+        if instruction != b' ' || self.always_tick {
+            self.wait_tick();
+        }
+
+        self.advance(code);
 
-            if self.trace {
-                self.dump_state(instruction);
+        match status {
+            Flow::Skip => {
+                // the skipped cell only causes a tick in always-tick mode
+                if self.always_tick {
+                    self.wait_tick();
+                }
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record_skip(&self.ip);
+                }
+                self.advance(code);
+                Ok(StepOutcome::Skipped { instruction, ip })
             }
+            _ => Ok(StepOutcome::Continued { instruction, ip }),
+        }
+    }
 
-            match self.execute(instruction, code) {
-                Ok(RuntimeStatus::Continue) => {}
-                Ok(RuntimeStatus::Stop) => return Ok(()),
-                Err(err) => return Err(err),
+    /// Executes an instruction, recording how to undo it in the history.
+    fn execute_with_history(&mut self, op: Op, code: &CodeBox) -> Result<Flow> {
+        let mut delta = self.capture(op.byte());
+        let status = self.execute_op(op, code);
+        // including the cell of the instruction, touched before it was executed
+        delta.touched = mem::take(&mut self.touched);
+        delta.events = mem::take(&mut self.consumed);
+        match status {
+            Ok(status) => {
+                if let Some(history) = &mut self.history {
+                    history.push(delta);
+                }
+                Ok(status)
             }
+            Err(e) => {
+                // leave the state as it was before the failing instruction,
+                // which uses the same input and directions when executed again
+                self.unrecord(&delta.events);
+                self.undo(delta);
+                Err(e)
+            }
+        }
+    }
+
+    /// Undoes the last step recorded in the history, returns false if there is none.
+    ///
+    /// Output already written cannot be taken back, and profile and coverage
+    /// counts are kept. Input read and directions chosen by `x` during the undone
+    /// steps are used again when executing them anew.
+    pub fn step_back(&mut self) -> bool {
+        let delta = match self.history.as_mut().and_then(History::pop) {
+            Some(delta) => delta,
+            None => return false,
+        };
 
-            if let Some(duration) = self.tick {
-                thread::sleep(duration);
+        self.unrecord(&delta.events);
+        self.undo(delta);
+        self.steps = self.steps.saturating_sub(1);
+        true
+    }
+
+    /// Removes the events of an undone step from the recording, they are
+    /// recorded again when the step is executed anew.
+    fn unrecord(&mut self, events: &[Event]) {
+        if let Some(recording) = &mut self.recording {
+            for event in events.iter().rev() {
+                if recording.events.last().map(Event::step) == Some(event.step()) {
+                    recording.events.pop();
+                }
             }
+        }
+    }
 
-            self.advance(code);
+    /// Saves what is needed to undo the given instruction.
+    fn capture(&self, instruction: u8) -> Delta {
+        let normal = matches!(self.state, ParserState::Normal);
+        let top = self.stack.top();
+        let len = top.len();
+
+        let stack = match history::popped_values(instruction) {
+            Some(k) if normal => StackDelta::Top {
+                len,
+                popped: top
+                    .values
+                    .range(len - cmp::min(k, len)..)
+                    .cloned()
+                    .collect(),
+                register: match instruction {
+                    b'&' => Some(top.register.clone()),
+                    _ => None,
+                },
+            },
+            _ if !normal => StackDelta::Top {
+                len,
+                popped: vec![],
+                register: None,
+            },
+            _ => match instruction {
+                b'}' => StackDelta::RightShift,
+                b'{' => StackDelta::LeftShift,
+                b'r' => StackDelta::Reverse,
+                _ => StackDelta::Full(self.stack.clone()),
+            },
+        };
+
+        let memory = match instruction {
+            b'p' if normal && len >= 2 => {
+                let pos = MemPos {
+                    x: top.values[len - 2].to_i64(),
+                    y: top.values[len - 1].to_i64(),
+                };
+                Some(MemoryDelta {
+                    previous: self.written(pos.x, pos.y).cloned(),
+                    pos,
+                })
+            }
+            _ => None,
+        };
+
+        Delta {
+            ip: self.ip.clone(),
+            dir: self.dir.clone(),
+            state: self.state.clone(),
+            stack,
+            memory,
+            events: vec![],
+            touched: vec![],
+            newline: self.newline,
         }
     }
 
+    fn undo(&mut self, delta: Delta) {
+        self.ip = delta.ip;
+        self.dir = delta.dir;
+        self.state = delta.state;
+        self.newline = delta.newline;
+        self.consumed.clear();
+
+        let top = self.stack.top_mut();
+        match delta.stack {
+            StackDelta::Top {
+                len,
+                popped,
+                register,
+            } => {
+                top.values.truncate(len - popped.len());
+                top.values.extend(popped);
+                if let Some(register) = register {
+                    top.register = register;
+                }
+            }
+            StackDelta::RightShift => top.lshift(),
+            StackDelta::LeftShift => top.rshift(),
+            StackDelta::Reverse => top.reverse(),
+            StackDelta::Full(stack) => self.stack = stack,
+        }
+
+        if let Some(m) = delta.memory {
+            self.write_cell(m.pos, m.previous);
+        }
+
+        if let Some(geometry) = &mut self.geometry {
+            for touch in delta.touched.into_iter().rev() {
+                geometry.untouch(touch);
+            }
+        }
+
+        if let Some(history) = &mut self.history {
+            for event in delta.events.into_iter().rev() {
+                history.push_redo(event);
+            }
+        }
+    }
+
+    fn wait_tick(&self) {
+        if let Some(duration) = self.tick {
+            thread::sleep(duration);
+        }
+    }
+
+    fn check_limits(&mut self) -> Result<()> {
+        if let Some(max) = self.limits.max_steps {
+            if self.steps >= max {
+                return Err(RuntimeError::StepLimitExceeded);
+            }
+        }
+
+        if let Some(max) = self.limits.max_duration {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if started.elapsed() > max {
+                return Err(RuntimeError::TimeLimitExceeded);
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn fetch(&self, code: &CodeBox) -> Option<u8> {
-        // fetch from map only if memory is dirty
-        if self.memory_is_dirty {
-            // R/W codebox override (backed by a map)
-            let pos = MemPos {
-                x: self.ip.chr as i64,
-                y: self.ip.line as i64,
-            };
-            if let Some(v) = self.memory.get(&pos) {
-                return Some(v.to_u8());
+        self.cell_at(code, self.ip.chr, self.ip.line)
+    }
+
+    /// Returns the instruction at the given position,
+    /// taking into account values written with `p`.
+    pub fn cell_at(&self, code: &CodeBox, chr: i64, line: i64) -> Option<u8> {
+        if let Some(op) = self.grid(code).and_then(|grid| grid.op(chr, line)) {
+            return Some(op.byte());
+        }
+        if let Some(v) = self.written(chr, line) {
+            return Some(grid::instruction(v, self.compat, code.is_unicode()));
+        }
+
+        // negative coordinates become too large
+        let cell = code.get(chr as usize, line as usize);
+        match self.compat {
+            Compat::Native if self.grows() && self.bounds(code).contains(chr, line) => {
+                Some(cell.unwrap_or(b' '))
+            }
+            Compat::Native => cell,
+            // the code is surrounded by empty cells
+            Compat::FishPy => Some(cell.unwrap_or(b' ')),
+        }
+    }
+
+    fn geometry(&mut self, code: &CodeBox) -> &mut Geometry {
+        self.geometry.get_or_insert_with(|| Geometry::new(code))
+    }
+
+    /// Makes the line of a cell at least as wide as the cell, like `fish.py`.
+    fn touch(&mut self, code: &CodeBox, x: i64, y: i64) {
+        let touch = self.geometry(code).touch(x, y);
+        if let (Some(touch), Some(_)) = (touch, &self.history) {
+            self.touched.push(touch);
+        }
+    }
+
+    /// Returns whether the codebox grows to include the cells written around it.
+    fn grows(&self) -> bool {
+        self.grow_code && self.compat == Compat::Native
+    }
+
+    /// Returns the rectangle the instruction pointer wraps around: the codebox,
+    /// grown to include the cells written around it with `grow_code`.
+    // kept out of `advance`, which runs at every step
+    #[inline(never)]
+    pub fn bounds(&self, code: &CodeBox) -> Bounds {
+        if let Some(grid) = self.grid(code) {
+            return grid.bounds;
+        }
+        let mut bounds = Bounds::new(code.width, code.height);
+        if self.grows() {
+            for pos in self.memory.keys() {
+                bounds.include(pos.x, pos.y);
+            }
+            // cells written in the code that ran before
+            for (pos, _) in self.grid.iter().flat_map(Grid::written_cells) {
+                bounds.include(pos.x, pos.y);
+            }
+        }
+        bounds
+    }
+
+    /// Returns the lines and columns of the code and of the cells written around it
+    /// with `grow_code`, for display: the bounds of the codebox can be huge.
+    pub fn view(&self, code: &CodeBox) -> View {
+        let mut view = View::new(code.width, code.height);
+        if self.grows() {
+            for (pos, _) in self.written_cells() {
+                view.include(pos.x, pos.y);
+            }
+        }
+        view
+    }
+
+    /// Returns the decoded code, unless it was decoded from another codebox.
+    #[inline]
+    fn grid(&self, code: &CodeBox) -> Option<&Grid> {
+        self.grid.as_ref().filter(|grid| grid.code_id == code.id)
+    }
+
+    /// Decodes the code if it is not already, moving the values written
+    /// in its cells from `memory` into it. Code decoded from another codebox
+    /// is released first.
+    #[inline]
+    fn load_grid(&mut self, code: &CodeBox) {
+        match &self.grid {
+            Some(grid) if grid.code_id == code.id => return,
+            Some(_) => self.release_grid(),
+            None => {}
+        }
+        let mut grid = Grid::new(code, self.compat);
+        self.memory.retain(|pos, v| {
+            let inside = grid.contains(pos.x, pos.y);
+            if inside {
+                grid.write(pos.x, pos.y, Some(v.clone()));
             }
+            !inside
+        });
+        if self.grows() {
+            grid.fit_bounds(self.memory.keys());
         }
+        self.grid = Some(grid);
+    }
 
-        code.get(self.ip.chr, self.ip.line)
+    /// Moves the values written in the code back to `memory`,
+    /// for them to apply to the next code that runs.
+    fn release_grid(&mut self) {
+        if let Some(grid) = self.grid.take() {
+            let written = grid.written_cells().map(|(pos, v)| (pos, v.clone()));
+            self.memory.extend(written);
+        }
+    }
+
+    /// Returns the value written with `p` in a cell, if any.
+    fn written(&self, x: i64, y: i64) -> Option<&Val> {
+        match &self.grid {
+            Some(grid) if grid.contains(x, y) => grid.written(x, y),
+            _ if self.memory.is_empty() => None,
+            _ => self.memory.get(&MemPos { x, y }),
+        }
+    }
+
+    /// Returns the number of cells written with `p`.
+    fn written_len(&self) -> usize {
+        self.memory.len() + self.grid.as_ref().map_or(0, Grid::written_len)
+    }
+
+    /// Writes a value in a cell, or removes the value written there if `None`.
+    fn write_cell(&mut self, pos: MemPos, v: Option<Val>) {
+        let grows = self.grows();
+        match &mut self.grid {
+            Some(grid) if grid.contains(pos.x, pos.y) => grid.write(pos.x, pos.y, v),
+            grid => match v {
+                Some(v) => {
+                    if let (Some(grid), true) = (grid, grows) {
+                        grid.bounds.include(pos.x, pos.y);
+                    }
+                    self.memory.insert(pos, v);
+                }
+                None => {
+                    self.memory.remove(&pos);
+                    if let (Some(grid), true) = (grid, grows) {
+                        grid.fit_bounds(self.memory.keys());
+                    }
+                }
+            },
+        }
     }
 
     pub fn execute(&mut self, instruction: u8, code: &CodeBox) -> Result<RuntimeStatus> {
+        match self.execute_op(Op::decode(instruction), code)? {
+            Flow::Continue => Ok(RuntimeStatus::Continue),
+            Flow::Skip => {
+                self.advance(code);
+                Ok(RuntimeStatus::Continue)
+            }
+            Flow::Stop => Ok(RuntimeStatus::Stop),
+        }
+    }
+
+    fn execute_op(&mut self, op: Op, code: &CodeBox) -> Result<Flow> {
         match self.state {
-            ParserState::Normal => return self.execute_instruction(instruction, code),
+            ParserState::Normal => {
+                self.popped.clear();
+                let status = self.execute_instruction(op, code);
+                if status.is_err() {
+                    // leave the stack as the failing instruction found it
+                    while let Some(v) = self.popped.pop() {
+                        self.stack.top_mut().push(v);
+                    }
+                }
+                return status;
+            }
             ParserState::SingleQuoted => {
-                match instruction as char {
+                match op {
                     // Exit quote mode
-                    '\'' => self.state = ParserState::Normal,
-                    _ => self.stack.top_mut().push(Val::Byte(instruction)),
+                    Op::SingleQuote => self.state = ParserState::Normal,
+                    _ => self.push_quoted(op.byte(), code),
                 }
             }
             ParserState::DoubleQuoted => {
-                match instruction as char {
+                match op {
                     // Exit quote mode
-                    '"' => self.state = ParserState::Normal,
-                    _ => self.stack.top_mut().push(Val::Byte(instruction)),
+                    Op::DoubleQuote => self.state = ParserState::Normal,
+                    _ => self.push_quoted(op.byte(), code),
                 }
             }
         }
-        Ok(RuntimeStatus::Continue)
+        Ok(Flow::Continue)
+    }
+
+    /// Pushes the cell under the instruction pointer in string mode.
+    fn push_quoted(&mut self, instruction: u8, code: &CodeBox) {
+        let v = if code.is_unicode() {
+            // the whole character, not only the instruction it stands for
+            let (x, y) = (self.ip.chr, self.ip.line);
+            self.written(x, y).cloned().unwrap_or_else(|| {
+                code.get_char(x as usize, y as usize)
+                    .map_or(Val::Byte(instruction), Val::from)
+            })
+        } else {
+            Val::Byte(instruction)
+        };
+        self.stack.top_mut().push(v);
     }
 
     #[inline]
     fn pop(&mut self) -> Result<Val> {
-        self.stack
+        let v = self
+            .stack
             .top_mut()
             .pop()
-            .ok_or(RuntimeError::StackUnderflow)
+            .ok_or(RuntimeError::StackUnderflow)?;
+        self.popped.push(v.clone());
+        match self.compat {
+            Compat::Native => Ok(v),
+            Compat::FishPy => compat::popped(v),
+        }
     }
 
-    fn execute_instruction(&mut self, instruction: u8, code: &CodeBox) -> Result<RuntimeStatus> {
-        match instruction {
+    fn execute_instruction(&mut self, op: Op, code: &CodeBox) -> Result<Flow> {
+        match op {
             // Enter quote mode
-            b'\'' => self.state = ParserState::SingleQuoted,
-            b'"' => self.state = ParserState::DoubleQuoted,
+            Op::SingleQuote => self.state = ParserState::SingleQuoted,
+            Op::DoubleQuote => self.state = ParserState::DoubleQuoted,
 
             // # Movement and execution
             // absolute direction change
-            b'>' => self.dir = Direction::Right,
-            b'<' => self.dir = Direction::Left,
-            b'^' => self.dir = Direction::Up,
-            b'v' => self.dir = Direction::Down,
+            Op::Right => self.dir = Direction::Right,
+            Op::Left => self.dir = Direction::Left,
+            Op::Up => self.dir = Direction::Up,
+            Op::Down => self.dir = Direction::Down,
 
             // mirrors
-            b'/' | b'\\' | b'|' | b'_' | b'#' => self.mirror(instruction),
+            Op::Slash
+            | Op::Backslash
+            | Op::VerticalMirror
+            | Op::HorizontalMirror
+            | Op::FullMirror => self.mirror(op),
 
             // random direction
-            b'x' => {
+            Op::Random => {
                 static DIRECTIONS: [Direction; 4] = [
                     Direction::Left,
                     Direction::Right,
@@ -304,108 +1229,140 @@ impl<R: Read, W: Write> Interpreter<R, W> {
                     Direction::Down,
                 ];
 
-                if let Some(dir) = DIRECTIONS.choose(&mut self.rng) {
-                    self.dir = dir.clone();
+                let dir = match self.history.as_mut().and_then(History::next_redo) {
+                    Some(Event::Random { dir, .. }) => dir,
+                    Some(_) => return Err(RuntimeError::ReplayDiverged),
+                    None => match &mut self.replay {
+                        Some(replay) => replay.next_random(self.steps)?,
+                        None => DIRECTIONS
+                            .choose(&mut self.rng)
+                            .cloned()
+                            .unwrap_or(Direction::Right),
+                    },
+                };
+
+                if let Some(recording) = &mut self.recording {
+                    recording.record_random(self.steps, dir.clone());
                 }
+                if self.history.is_some() {
+                    self.consumed.push(Event::Random {
+                        step: self.steps,
+                        dir: dir.clone(),
+                        elapsed_ms: 0,
+                    });
+                }
+                self.dir = dir;
             }
 
             // skip the following instruction
-            b'!' => self.advance(code),
+            Op::Trampoline => return Ok(Flow::Skip),
 
             // Conditional trampoline - pop one value off the stack.
             // The next instruction is only executed if the popped value is non-zero.
-            b'?' => {
-                match self.stack.top_mut().pop() {
-                    Some(v) => {
-                        if v.to_i64() == 0 {
-                            self.advance(code);
-                        }
+            Op::Conditional => {
+                let v = self.pop()?;
+                let zero = match self.compat {
+                    Compat::Native => v.to_i64() == 0,
+                    Compat::FishPy => {
+                        v.partial_cmp_real(&Val::Byte(0)) == Some(cmp::Ordering::Equal)
                     }
-                    None => return Err(RuntimeError::StackUnderflow),
                 };
+                if zero {
+                    return Ok(Flow::Skip);
+                }
             }
 
             // jump to (x,y)
-            b'.' => self.jump(code)?,
+            Op::Jump => self.jump(code)?,
 
             // # Literals and operators
             // literal values
-            b'0'..=b'9' | b'a'..=b'f' => {
-                if let Some(val) = (instruction as char).to_digit(16) {
-                    self.stack.top_mut().push((val as u8).into());
-                }
-            }
+            Op::Literal(val) => self.stack.top_mut().push(Val::Byte(val)),
 
             // arithmetic operations
-            b'+' => self.add()?,
-            b'-' => self.sub()?,
-            b'*' => self.mul()?,
-            b',' => self.div()?,
-            b'%' => self.rem()?,
+            Op::Add => self.add()?,
+            Op::Sub => self.sub()?,
+            Op::Mul => self.mul()?,
+            Op::Div => self.div()?,
+            Op::Rem => self.rem()?,
 
             // comparison tests
-            b'=' => self.equals()?,
-            b')' => self.gt()?,
-            b'(' => self.lt()?,
+            Op::Equals => self.equals()?,
+            Op::Greater => self.gt()?,
+            Op::Less => self.lt()?,
 
             // # Stack manipulation
             // Duplicate the top value on the stack
-            b':' => self
+            Op::Dup => self
                 .stack
                 .top_mut()
                 .dup()
                 .or(Err(RuntimeError::StackUnderflow))?,
             // Remove the top value from the stack
-            b'~' => self
+            Op::Drop => self
                 .stack
                 .top_mut()
                 .drop()
                 .or(Err(RuntimeError::StackUnderflow))?,
             // Swap the top two values on the stack
-            b'$' => self
+            Op::Swap => self
                 .stack
                 .top_mut()
                 .swap()
                 .or(Err(RuntimeError::StackUnderflow))?,
             // Swap the top three values on the stack
-            b'@' => self
+            Op::Swap2 => self
                 .stack
                 .top_mut()
                 .swap2()
                 .or(Err(RuntimeError::StackUnderflow))?,
             // Shift the entire stack to the right
-            b'}' => self.stack.top_mut().rshift(),
+            Op::RightShift => {
+                self.check_shift()?;
+                self.stack.top_mut().rshift()
+            }
             // Shift the entire stack to the left
-            b'{' => self.stack.top_mut().lshift(),
+            Op::LeftShift => {
+                self.check_shift()?;
+                self.stack.top_mut().lshift()
+            }
             // Reverse the stack
-            b'r' => self.stack.top_mut().values.reverse(),
+            Op::Reverse => self.stack.top_mut().reverse(),
             // Push the length of the stack onto the stack
-            b'l' => {
-                let l = self.stack.top_mut().values.len();
-                self.stack.top_mut().values.push(Val::Int(l as i64));
+            Op::Length => {
+                let top = self.stack.top_mut();
+                top.push(Val::Int(top.len() as i64));
             }
 
             // # Stack of stacks
             // Pop x off the stack and create a new stack, moving x values.
-            b'[' => {
-                let v = self.pop()?;
+            Op::NewStack => {
+                let v = self.pop()?.to_i64();
+                let count = match self.compat {
+                    Compat::Native => usize::try_from(v).or(Err(RuntimeError::StackUnderflow))?,
+                    // slicing semantics of fish.py
+                    Compat::FishPy => {
+                        let len = self.stack.top().len() as i64;
+                        (if v < 0 { len + v } else { v }).clamp(0, len) as usize
+                    }
+                };
                 self.stack
-                    .push_stack(v.to_i64() as usize)
+                    .push_stack(count)
                     .or(Err(RuntimeError::StackUnderflow))?;
             }
             // Remove the current stack, moving its values to the top of the underlying stack
-            b']' => self.stack.pop_stack(),
+            Op::RemoveStack => self.stack.pop_stack(),
 
             // # I/O
             // Output value as character
-            b'o' => self.char_output()?,
+            Op::CharOutput => self.char_output()?,
             // Output value as number
-            b'n' => self.num_output()?,
+            Op::NumOutput => self.num_output()?,
             // Input byte
-            b'i' => self.input()?,
+            Op::Input => self.input()?,
 
             // register operation
-            b'&' => self
+            Op::Register => self
                 .stack
                 .top_mut()
                 .switch_register()
@@ -413,39 +1370,39 @@ impl<R: Read, W: Write> Interpreter<R, W> {
 
             // # Memory operations
             // Push from memory
-            b'g' => self.read_memory(code)?,
+            Op::Get => self.read_memory(code)?,
             // Pop to memory
-            b'p' => self.write_memory(code)?,
+            Op::Put => self.write_memory(code)?,
 
             // end execution
-            b';' => return Ok(RuntimeStatus::Stop),
+            Op::End => return Ok(Flow::Stop),
 
             // nop
-            b' ' => {}
+            Op::Nop => {}
 
-            _ => return Err(RuntimeError::InvalidInstruction),
+            Op::Invalid(_) => return Err(RuntimeError::InvalidInstruction),
         }
-        Ok(RuntimeStatus::Continue)
+        Ok(Flow::Continue)
     }
 
-    fn advance(&mut self, code: &CodeBox) {
-        match self.dir {
-            Direction::Right => self.ip.chr = self.ip.chr.checked_add(1).unwrap_or(0),
-            Direction::Left => self.ip.chr = self.ip.chr.checked_sub(1).unwrap_or(code.width - 1),
-            Direction::Up => self.ip.line = self.ip.line.checked_sub(1).unwrap_or(code.height - 1),
-            Direction::Down => self.ip.line = self.ip.line.checked_add(1).unwrap_or(0),
-        }
-        if self.ip.chr >= code.width {
-            self.ip.chr = 0;
+    /// Moves the instruction pointer one cell in the current direction,
+    /// wrapping around the edges of the codebox.
+    pub fn advance(&mut self, code: &CodeBox) {
+        if self.compat == Compat::FishPy {
+            let geometry = self.geometry.get_or_insert_with(|| Geometry::new(code));
+            geometry.advance(&mut self.ip, &self.dir);
+            return;
         }
-        if self.ip.line >= code.height {
-            self.ip.line = 0;
+
+        match &self.grid {
+            Some(grid) if grid.code_id == code.id => grid.bounds.advance(&mut self.ip, &self.dir),
+            _ => self.bounds(code).advance(&mut self.ip, &self.dir),
         }
     }
 
-    fn mirror(&mut self, instruction: u8) {
-        match instruction {
-            b'/' => {
+    fn mirror(&mut self, op: Op) {
+        match op {
+            Op::Slash => {
                 self.dir = match self.dir {
                     Direction::Right => Direction::Up,
                     Direction::Left => Direction::Down,
@@ -453,7 +1410,7 @@ impl<R: Read, W: Write> Interpreter<R, W> {
                     Direction::Down => Direction::Left,
                 }
             }
-            b'\\' => {
+            Op::Backslash => {
                 self.dir = match self.dir {
                     Direction::Right => Direction::Down,
                     Direction::Left => Direction::Up,
@@ -461,7 +1418,7 @@ impl<R: Read, W: Write> Interpreter<R, W> {
                     Direction::Down => Direction::Right,
                 }
             }
-            b'|' => {
+            Op::VerticalMirror => {
                 self.dir = match self.dir {
                     Direction::Right => Direction::Left,
                     Direction::Left => Direction::Right,
@@ -469,7 +1426,7 @@ impl<R: Read, W: Write> Interpreter<R, W> {
                     Direction::Down => Direction::Down,
                 }
             }
-            b'_' => {
+            Op::HorizontalMirror => {
                 self.dir = match self.dir {
                     Direction::Right => Direction::Right,
                     Direction::Left => Direction::Left,
@@ -477,7 +1434,7 @@ impl<R: Read, W: Write> Interpreter<R, W> {
                     Direction::Down => Direction::Up,
                 }
             }
-            b'#' => {
+            Op::FullMirror => {
                 self.dir = match self.dir {
                     Direction::Right => Direction::Left,
                     Direction::Left => Direction::Right,
@@ -493,20 +1450,23 @@ impl<R: Read, W: Write> Interpreter<R, W> {
         let y = self.pop()?.to_i64();
         let x = self.pop()?.to_i64();
 
-        if x < 0 || y < 0 {
-            return Err(RuntimeError::InvalidIpPosition);
+        // wrapping is left to advance
+        if self.compat == Compat::FishPy {
+            if x < 0 || y < 0 {
+                return Err(RuntimeError::InvalidIpPosition);
+            }
+            self.ip = InstructionPtr { chr: x, line: y };
+            return Ok(());
         }
 
-        self.ip.chr = x as usize;
-        self.ip.line = y as usize;
-
-        if self.ip.chr >= code.width {
-            self.ip.chr = 0;
-        }
-        if self.ip.line >= code.height {
-            self.ip.line = 0;
+        let bounds = self.bounds(code);
+        if x < bounds.min_x || y < bounds.min_y {
+            return Err(RuntimeError::InvalidIpPosition);
         }
 
+        self.ip.chr = if x > bounds.max_x { bounds.min_x } else { x };
+        self.ip.line = if y > bounds.max_y { bounds.min_y } else { y };
+
         Ok(())
     }
 
@@ -514,7 +1474,9 @@ impl<R: Read, W: Write> Interpreter<R, W> {
         let x = self.pop()?;
         let y = self.pop()?;
 
-        let res = y.checked_add(&x).ok_or(RuntimeError::IntegerOverflow)?;
+        let res = y
+            .add_with(&x, self.overflow)
+            .ok_or(RuntimeError::IntegerOverflow)?;
         self.stack.top_mut().push(res);
         Ok(())
     }
@@ -523,7 +1485,9 @@ impl<R: Read, W: Write> Interpreter<R, W> {
         let x = self.pop()?;
         let y = self.pop()?;
 
-        let res = y.checked_sub(&x).ok_or(RuntimeError::IntegerOverflow)?;
+        let res = y
+            .sub_with(&x, self.overflow)
+            .ok_or(RuntimeError::IntegerOverflow)?;
         self.stack.top_mut().push(res);
         Ok(())
     }
@@ -532,7 +1496,9 @@ impl<R: Read, W: Write> Interpreter<R, W> {
         let x = self.pop()?;
         let y = self.pop()?;
 
-        let res = y.checked_mul(&x).ok_or(RuntimeError::IntegerOverflow)?;
+        let res = y
+            .mul_with(&x, self.overflow)
+            .ok_or(RuntimeError::IntegerOverflow)?;
         self.stack.top_mut().push(res);
         Ok(())
     }
@@ -541,6 +1507,19 @@ impl<R: Read, W: Write> Interpreter<R, W> {
         let x = self.pop()?;
         let y = self.pop()?;
 
+        #[cfg(feature = "rational")]
+        {
+            if self.exact_division {
+                let res = y.div_exact(&x).ok_or(RuntimeError::DivideByZero)?;
+                self.stack.top_mut().push(res);
+                return Ok(());
+            }
+        }
+
+        if self.compat == Compat::FishPy && x.to_f64() == 0.0 {
+            return Err(RuntimeError::DivideByZero);
+        }
+
         let res = y.to_f64() / x.to_f64();
         if res.is_infinite() {
             return Err(RuntimeError::DivideByZero);
@@ -551,86 +1530,185 @@ impl<R: Read, W: Write> Interpreter<R, W> {
     }
 
     fn rem(&mut self) -> Result<()> {
-        let x = self.pop()?.to_i64();
-        let y = self.pop()?.to_i64();
+        let x = self.pop()?;
+        let y = self.pop()?;
 
-        if x == 0 {
-            return Err(RuntimeError::DivideByZero);
+        if let (Compat::FishPy, Val::Float(_), _) | (Compat::FishPy, _, Val::Float(_)) =
+            (self.compat, &x, &y)
+        {
+            if x.to_f64() == 0.0 {
+                return Err(RuntimeError::DivideByZero);
+            }
+            let res = compat::float_rem(y.to_f64(), x.to_f64());
+            self.stack.top_mut().push(Val::Float(res));
+            return Ok(());
         }
 
-        let rem = y % x;
-        let modulo = rem.checked_add(x).ok_or(RuntimeError::IntegerOverflow)? % x;
-
-        self.stack.top_mut().push(Val::Int(modulo));
+        let res = y.checked_rem(&x).ok_or(RuntimeError::DivideByZero)?;
+        self.stack.top_mut().push(res);
         Ok(())
     }
 
     fn equals(&mut self) -> Result<()> {
-        let x = self.pop()?.to_i64();
-        let y = self.pop()?.to_i64();
+        let x = self.pop()?;
+        let y = self.pop()?;
 
-        let res = y == x;
+        let res = self.compare(&y, &x) == Some(cmp::Ordering::Equal);
         self.stack.top_mut().push(Val::Byte(res as u8));
         Ok(())
     }
 
     fn gt(&mut self) -> Result<()> {
-        let x = self.pop()?.to_i64();
-        let y = self.pop()?.to_i64();
+        let x = self.pop()?;
+        let y = self.pop()?;
 
-        let res = y > x;
+        let res = self.compare(&y, &x) == Some(cmp::Ordering::Greater);
         self.stack.top_mut().push(Val::Byte(res as u8));
         Ok(())
     }
 
     fn lt(&mut self) -> Result<()> {
-        let x = self.pop()?.to_i64();
-        let y = self.pop()?.to_i64();
+        let x = self.pop()?;
+        let y = self.pop()?;
 
-        let res = y < x;
+        let res = self.compare(&y, &x) == Some(cmp::Ordering::Less);
         self.stack.top_mut().push(Val::Byte(res as u8));
         Ok(())
     }
 
+    fn compare(&self, y: &Val, x: &Val) -> Option<cmp::Ordering> {
+        match self.compat {
+            Compat::Native => Some(y.cmp_int(x)),
+            Compat::FishPy => y.partial_cmp_real(x),
+        }
+    }
+
+    fn check_shift(&self) -> Result<()> {
+        if self.compat == Compat::FishPy && self.stack.top().is_empty() {
+            return Err(RuntimeError::StackUnderflow);
+        }
+        Ok(())
+    }
+
     fn char_output(&mut self) -> Result<()> {
-        let c = self.pop()?.to_u8() as char;
+        let v = self.pop()?;
+        let c = if self.unicode_io() {
+            u32::try_from(v.to_i64())
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or(RuntimeError::InvalidCharacter)?
+        } else {
+            v.to_u8() as char
+        };
+        self.newline = Some(c == '\n');
         write!(&mut self.output, "{}", c).or(Err(RuntimeError::IOError))
     }
 
     fn num_output(&mut self) -> Result<()> {
-        match self.pop()? {
-            Val::Float(f) => write!(&mut self.output, "{}", f).or(Err(RuntimeError::IOError)),
-            v => write!(&mut self.output, "{}", v.to_i64()).or(Err(RuntimeError::IOError)),
+        let s = match self.pop()? {
+            Val::Float(f) => self.format_float(f)?,
+            #[cfg(feature = "bignum")]
+            Val::Big(b) => b.to_string(),
+            #[cfg(feature = "rational")]
+            v @ Val::Ratio(_) => self.format_float(v.to_f64())?,
+            v => v.to_i64().to_string(),
+        };
+        self.newline = Some(false);
+        write!(&mut self.output, "{}", s).or(Err(RuntimeError::IOError))
+    }
+
+    /// Returns whether `i`, `o` and strings use Unicode characters instead of bytes.
+    fn unicode_io(&self) -> bool {
+        self.unicode || self.compat == Compat::FishPy
+    }
+
+    fn format_float(&self, f: f64) -> Result<String> {
+        match (self.number_format, self.compat) {
+            (NumberFormat::Native, Compat::Native) => Ok(f.to_string()),
+            _ => python_float(f),
         }
     }
 
     fn input(&mut self) -> Result<()> {
-        match self.input.next() {
-            Some(Ok(b)) => self.stack.top_mut().push(Val::Byte(b)),
-            Some(Err(_)) => return Err(RuntimeError::IOError),
-            None => self.stack.top_mut().push(Val::Int(-1)),
-        }
+        let val = if self.unicode_io() {
+            self.read_char()?.map(Val::from)
+        } else {
+            self.read_byte()?.map(Val::Byte)
+        };
+        self.stack.top_mut().push(val.unwrap_or(Val::Int(-1)));
         Ok(())
     }
 
+    /// Reads a UTF-8 character, one byte at a time so that each byte is recorded.
+    fn read_char(&mut self) -> Result<Option<char>> {
+        let first = match self.read_byte()? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let len = match first {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Err(RuntimeError::IOError),
+        };
+
+        let mut bytes = vec![first];
+        for _ in 1..len {
+            bytes.push(self.read_byte()?.ok_or(RuntimeError::IOError)?);
+        }
+        std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .map(Some)
+            .ok_or(RuntimeError::IOError)
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let byte = match self.history.as_mut().and_then(History::next_redo) {
+            Some(Event::Input { byte, .. }) => byte,
+            Some(_) => return Err(RuntimeError::ReplayDiverged),
+            None => match &mut self.replay {
+                Some(replay) => replay.next_input(self.steps)?,
+                None => match self.input.next() {
+                    Some(Ok(b)) => Some(b),
+                    Some(Err(_)) => return Err(RuntimeError::IOError),
+                    None => None,
+                },
+            },
+        };
+
+        if let Some(recording) = &mut self.recording {
+            recording.record_input(self.steps, byte);
+        }
+        if self.history.is_some() {
+            self.consumed.push(Event::Input {
+                step: self.steps,
+                byte,
+                elapsed_ms: 0,
+            });
+        }
+
+        Ok(byte)
+    }
+
     fn get_memory(&self, code: &CodeBox, x: i64, y: i64) -> Val {
-        // fetch from map only if memory is dirty
-        if self.memory_is_dirty {
-            if let Some(v) = self.memory.get(&MemPos { x, y }) {
-                return v.clone();
-            }
+        if let Some(v) = self.written(x, y) {
+            return v.clone();
         }
 
-        let b = code.get(x as usize, y as usize);
-        Val::Byte(match b {
-            Some(b' ') | None => 0,
-            Some(b) => b,
-        })
+        match code.get_char(x as usize, y as usize) {
+            Some(' ') | None => Val::Byte(0),
+            Some(c) => Val::from(c),
+        }
     }
 
     fn read_memory(&mut self, code: &CodeBox) -> Result<()> {
         let y = self.pop()?.to_i64();
         let x = self.pop()?.to_i64();
+        if self.compat == Compat::FishPy {
+            self.touch(code, x, y);
+        }
 
         let val = self.get_memory(code, x, y);
         self.stack.top_mut().push(val);
@@ -641,13 +1719,21 @@ impl<R: Read, W: Write> Interpreter<R, W> {
         let y = self.pop()?.to_i64();
         let x = self.pop()?.to_i64();
         let v = self.pop()?;
+        if self.compat == Compat::FishPy {
+            self.touch(code, x, y);
+        }
 
         let val = self.get_memory(code, x, y);
 
-        // abort if we don't actually change memory
-        if v != val {
-            self.memory.insert(MemPos { x, y }, v);
-            self.memory_is_dirty = true;
+        // abort if we don't actually change memory, unless the write grows the codebox
+        if v != val || self.grows() {
+            if let Some(max) = self.limits.max_memory_cells {
+                if self.written_len() >= max && self.written(x, y).is_none() {
+                    return Err(RuntimeError::MemoryLimitExceeded);
+                }
+            }
+            self.load_grid(code);
+            self.write_cell(MemPos { x, y }, Some(v));
         }
 
         Ok(())
@@ -678,7 +1764,7 @@ mod tests {
     #[test]
     fn codebox_data_is_ok() {
         let cb = CodeBox::load_from_string("str");
-        assert_eq!(cb.data[0], vec![b's', b't', b'r']);
+        assert_eq!(cb.data[0], vec!['s', 't', 'r']);
     }
 
     #[test]
@@ -724,25 +1810,39 @@ mod tests {
         assert_eq!(v, Some(b' '));
     }
 
+    #[test]
+    fn codebox_decode_utf8() {
+        let mut cb = CodeBox::load_from_string("hé\n☺");
+        assert_eq!(cb.width, 3);
+        assert_eq!(cb.get(2, 0), Some(0xa9));
+
+        cb.decode_utf8();
+        assert_eq!(cb.width, 2);
+        assert_eq!(cb.get_char(1, 0), Some('é'));
+        assert_eq!(cb.get(1, 0), Some(0xe9));
+        assert_eq!(cb.get(0, 1), Some(0));
+        assert_eq!(cb.cells(), vec![vec![0x68, 0xe9], vec![0x263a]]);
+    }
+
     #[test]
     fn codebox_set() {
         let mut cb = CodeBox::load_from_string("str");
         cb.set(0, 0, b'a');
-        assert_eq!(cb.data[0], vec![b'a', b't', b'r']);
+        assert_eq!(cb.data[0], vec!['a', 't', 'r']);
     }
 
     #[test]
     fn codebox_set_empty() {
         let mut cb = CodeBox::load_from_string("str\nmore\nlines");
         cb.set(4, 0, b'a');
-        assert_eq!(cb.data[0], vec![b's', b't', b'r', b' ', b'a']);
+        assert_eq!(cb.data[0], vec!['s', 't', 'r', ' ', 'a']);
     }
 
     #[test]
     fn codebox_set_invalid_x() {
         let mut cb = CodeBox::load_from_string("str\nmore\nlines");
         cb.set(5, 0, b'a');
-        assert_eq!(cb.data[0], vec![b's', b't', b'r']);
+        assert_eq!(cb.data[0], vec!['s', 't', 'r']);
     }
 
     #[test]
@@ -0,0 +1,125 @@
+use crate::{Result, RuntimeError};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// How the `n` instruction prints numbers.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumberFormat {
+    /// Rust formatting: floats never use scientific notation.
+    #[default]
+    Native,
+    /// Same output as the reference interpreter: floats with an integer value
+    /// are printed as integers, others as Python's `repr`. Infinite and NaN floats
+    /// are errors, as the reference interpreter fails to turn them into integers.
+    Python,
+}
+
+/// Formats a float the way `fish.py` prints it with `n`, failing like it does
+/// on infinite and NaN floats.
+pub fn python_float(f: f64) -> Result<String> {
+    if !f.is_finite() {
+        return Err(RuntimeError::NonFiniteFloat);
+    }
+    if f.fract() == 0.0 {
+        return Ok(integral_float(f));
+    }
+
+    // non-integral floats are smaller than 2^52, so only small values use an exponent
+    if f.abs() < 1e-4 {
+        let s = format!("{:e}", f);
+        return Ok(match s.split_once('e') {
+            Some((mantissa, exp)) => match exp.strip_prefix('-') {
+                Some(exp) => format!("{}e-{:0>2}", mantissa, exp),
+                None => format!("{}e+{:0>2}", mantissa, exp),
+            },
+            None => s,
+        });
+    }
+
+    Ok(f.to_string())
+}
+
+/// Returns the exact decimal value of a float with no fractional part.
+fn integral_float(f: f64) -> String {
+    if f.abs() < 9.2e18 {
+        return (f as i64).to_string();
+    }
+
+    let bits = f.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i32 - 1075;
+    let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+
+    // little-endian base 10^9 digits of mantissa * 2^exponent, exponent is positive here
+    const BASE: u64 = 1_000_000_000;
+    let mut limbs = vec![
+        mantissa % BASE,
+        mantissa / BASE % BASE,
+        mantissa / BASE / BASE,
+    ];
+    for _ in 0..exponent {
+        let mut carry = 0;
+        for limb in limbs.iter_mut() {
+            let v = *limb * 2 + carry;
+            *limb = v % BASE;
+            carry = v / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry);
+        }
+    }
+    while limbs.len() > 1 && limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+
+    let mut s = String::new();
+    if f < 0.0 {
+        s.push('-');
+    }
+    let mut limbs = limbs.iter().rev();
+    if let Some(first) = limbs.next() {
+        let _ = write!(s, "{}", first);
+    }
+    for limb in limbs {
+        let _ = write!(s, "{:09}", limb);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn python_float_works() {
+        assert_eq!(python_float(6.0).unwrap(), "6");
+        assert_eq!(python_float(-0.0).unwrap(), "0");
+        assert_eq!(python_float(0.1).unwrap(), "0.1");
+        assert_eq!(python_float(-2.5).unwrap(), "-2.5");
+        assert_eq!(python_float(123456.789).unwrap(), "123456.789");
+        assert_eq!(python_float(0.0001).unwrap(), "0.0001");
+        assert_eq!(python_float(1e-5).unwrap(), "1e-05");
+        assert_eq!(python_float(1.5e-7).unwrap(), "1.5e-07");
+        assert_eq!(python_float(1.2345678e-10).unwrap(), "1.2345678e-10");
+        assert_eq!(python_float(5e-324).unwrap(), "5e-324");
+        assert_eq!(python_float(f64::NAN), Err(RuntimeError::NonFiniteFloat));
+        assert_eq!(
+            python_float(f64::NEG_INFINITY),
+            Err(RuntimeError::NonFiniteFloat)
+        );
+    }
+
+    #[test]
+    fn integral_floats_are_exact() {
+        assert_eq!(python_float(1e16).unwrap(), "10000000000000000");
+        assert_eq!(python_float(1e23).unwrap(), "99999999999999991611392");
+        assert_eq!(
+            python_float(2f64.powi(70)).unwrap(),
+            "1180591620717411303424"
+        );
+        assert_eq!(
+            python_float(-1e100).unwrap(),
+            "-10000000000000000159028911097599180468360808563945281389781327557747838772170381060813469985856815104"
+        );
+    }
+}
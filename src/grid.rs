@@ -0,0 +1,293 @@
+use crate::op::Op;
+use crate::{compat, CodeBox, Compat, Direction, InstructionPtr, MemPos, Val};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+/// Returns the instruction stored in a cell written with `p`.
+pub(crate) fn instruction(v: &Val, compat: Compat, unicode: bool) -> u8 {
+    match compat {
+        // characters of a Unicode code are not truncated into instructions
+        Compat::Native if unicode => u8::try_from(v.to_i64()).unwrap_or_default(),
+        Compat::Native => v.to_u8(),
+        Compat::FishPy => compat::instruction(v),
+    }
+}
+
+/// Decoded instructions of a codebox, along with the values written in its cells with `p`.
+#[derive(Clone, Debug)]
+pub(crate) struct Grid {
+    width: usize,
+    height: usize,
+    /// Current instruction of each cell, row by row.
+    ops: Vec<Op>,
+    /// Instructions of the code, restored when a written value is removed.
+    code: Vec<Op>,
+    written: Vec<Option<Val>>,
+    written_len: usize,
+    /// Rectangle the instruction pointer wraps around.
+    pub bounds: Bounds,
+    compat: Compat,
+    unicode: bool,
+    /// See `CodeBox::id`.
+    pub code_id: u64,
+}
+
+impl Grid {
+    pub fn new(code: &CodeBox, compat: Compat) -> Self {
+        let (width, height) = (code.width(), code.height());
+        let mut ops = Vec::with_capacity(width * height);
+        for line in &code.data {
+            ops.extend(line.iter().map(|&c| Op::decode(CodeBox::instruction(c))));
+            ops.resize(ops.len() + width - line.len(), Op::Nop);
+        }
+        Grid {
+            width,
+            height,
+            code: ops.clone(),
+            written: vec![None; ops.len()],
+            ops,
+            written_len: 0,
+            bounds: Bounds::new(width, height),
+            compat,
+            unicode: code.is_unicode(),
+            code_id: code.id,
+        }
+    }
+
+    /// Returns the instruction of a cell, `None` outside of the code.
+    #[inline]
+    pub fn op(&self, x: i64, y: i64) -> Option<Op> {
+        // negative coordinates become too large
+        let (x, y) = (x as usize, y as usize);
+        if x < self.width && y < self.height {
+            Some(self.ops[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    fn index(&self, x: i64, y: i64) -> Option<usize> {
+        if (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y) {
+            Some(y as usize * self.width + x as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        self.index(x, y).is_some()
+    }
+
+    /// Returns the value written in a cell of the code, if any.
+    pub fn written(&self, x: i64, y: i64) -> Option<&Val> {
+        self.written[self.index(x, y)?].as_ref()
+    }
+
+    /// Number of cells holding a written value.
+    pub fn written_len(&self) -> usize {
+        self.written_len
+    }
+
+    /// Writes a value in a cell of the code, or removes the written value if `None`.
+    /// Does nothing outside of the code.
+    pub fn write(&mut self, x: i64, y: i64, value: Option<Val>) {
+        let i = match self.index(x, y) {
+            Some(i) => i,
+            None => return,
+        };
+        let was_written = self.written[i].is_some();
+        match value {
+            Some(v) => {
+                self.ops[i] = Op::decode(instruction(&v, self.compat, self.unicode));
+                self.written[i] = Some(v);
+            }
+            None => {
+                self.ops[i] = self.code[i];
+                self.written[i] = None;
+            }
+        }
+        match (was_written, self.written[i].is_some()) {
+            (false, true) => self.written_len += 1,
+            (true, false) => self.written_len -= 1,
+            _ => {}
+        }
+    }
+
+    /// Makes the bounds hold the code and the given cells, and nothing more.
+    pub fn fit_bounds<'a>(&mut self, cells: impl Iterator<Item = &'a MemPos>) {
+        self.bounds = Bounds::new(self.width, self.height);
+        for pos in cells {
+            self.bounds.include(pos.x, pos.y);
+        }
+    }
+
+    /// Returns the written cells, row by row.
+    pub fn written_cells(&self) -> impl Iterator<Item = (MemPos, &Val)> {
+        let width = self.width;
+        self.written.iter().enumerate().filter_map(move |(i, v)| {
+            let pos = MemPos {
+                x: (i % width) as i64,
+                y: (i / width) as i64,
+            };
+            v.as_ref().map(|v| (pos, v))
+        })
+    }
+}
+
+/// Smallest rectangle holding the code and the cells written around it,
+/// which the instruction pointer wraps around.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Bounds {
+    pub min_x: i64,
+    pub min_y: i64,
+    pub max_x: i64,
+    pub max_y: i64,
+}
+
+impl Bounds {
+    /// Returns the bounds of a codebox of the given size, which has at least one cell.
+    pub fn new(width: usize, height: usize) -> Self {
+        Bounds {
+            min_x: 0,
+            min_y: 0,
+            max_x: width.max(1) as i64 - 1,
+            max_y: height.max(1) as i64 - 1,
+        }
+    }
+
+    /// Grows the bounds to include the given cell.
+    pub(crate) fn include(&mut self, x: i64, y: i64) {
+        self.min_x = self.min_x.min(x);
+        self.min_y = self.min_y.min(y);
+        self.max_x = self.max_x.max(x);
+        self.max_y = self.max_y.max(y);
+    }
+
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        (self.min_x..=self.max_x).contains(&x) && (self.min_y..=self.max_y).contains(&y)
+    }
+
+    /// Moves the instruction pointer one cell, wrapping around the edges.
+    #[inline]
+    pub(crate) fn advance(&self, ip: &mut InstructionPtr, dir: &Direction) {
+        match dir {
+            Direction::Right if ip.chr >= self.max_x => ip.chr = self.min_x,
+            Direction::Right => ip.chr += 1,
+            Direction::Left if ip.chr <= self.min_x => ip.chr = self.max_x,
+            Direction::Left => ip.chr -= 1,
+            Direction::Down if ip.line >= self.max_y => ip.line = self.min_y,
+            Direction::Down => ip.line += 1,
+            Direction::Up if ip.line <= self.min_y => ip.line = self.max_y,
+            Direction::Up => ip.line -= 1,
+        }
+    }
+}
+
+/// Lines and columns of a codebox worth showing: those of the code and of the cells
+/// included around it. The codebox can grow far away from the code, so the lines
+/// and columns in between are elided.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct View {
+    columns: BTreeSet<i64>,
+    lines: BTreeSet<i64>,
+}
+
+impl View {
+    /// Returns the view of a codebox of the given size, which has at least one cell.
+    pub fn new(width: usize, height: usize) -> Self {
+        View {
+            columns: (0..width.max(1) as i64).collect(),
+            lines: (0..height.max(1) as i64).collect(),
+        }
+    }
+
+    /// Shows the line and the column of the given cell.
+    pub fn include(&mut self, x: i64, y: i64) {
+        self.columns.insert(x);
+        self.lines.insert(y);
+    }
+
+    /// Returns the columns to show from left to right, with `None` where columns are elided.
+    pub fn columns(&self) -> Vec<Option<i64>> {
+        with_gaps(&self.columns)
+    }
+
+    /// Returns the lines to show from top to bottom, with `None` where lines are elided.
+    pub fn lines(&self) -> Vec<Option<i64>> {
+        with_gaps(&self.lines)
+    }
+}
+
+fn with_gaps(values: &BTreeSet<i64>) -> Vec<Option<i64>> {
+    let mut shown = vec![];
+    let mut previous = None;
+    for &v in values {
+        if previous.is_some_and(|p: i64| p + 1 != v) {
+            shown.push(None);
+        }
+        shown.push(Some(v));
+        previous = Some(v);
+    }
+    shown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_cells_are_restored() {
+        let code = CodeBox::load_from_string("1n;\n+");
+        let mut grid = Grid::new(&code, Compat::Native);
+        assert_eq!(grid.op(1, 0), Some(Op::NumOutput));
+        assert_eq!(grid.op(2, 1), Some(Op::Nop));
+        assert_eq!(grid.op(3, 0), None);
+        assert_eq!(grid.op(-1, 0), None);
+
+        grid.write(1, 0, Some(Val::Byte(b'o')));
+        grid.write(5, 0, Some(Val::Byte(b'o')));
+        assert_eq!(grid.op(1, 0), Some(Op::CharOutput));
+        assert_eq!(grid.written(1, 0), Some(&Val::Byte(b'o')));
+        assert_eq!(grid.written_len(), 1);
+
+        grid.write(1, 0, None);
+        assert_eq!(grid.op(1, 0), Some(Op::NumOutput));
+        assert_eq!(grid.written_cells().count(), 0);
+    }
+
+    #[test]
+    fn bounds_wrap_around_written_cells() {
+        let code = CodeBox::load_from_string("abc\nd");
+        let mut bounds = Bounds::new(code.width(), code.height());
+        let mut ip = InstructionPtr { chr: 2, line: 1 };
+
+        bounds.advance(&mut ip, &Direction::Right);
+        assert_eq!(ip, InstructionPtr { chr: 0, line: 1 });
+
+        bounds.include(-2, 4);
+        bounds.advance(&mut ip, &Direction::Down);
+        assert_eq!(ip, InstructionPtr { chr: 0, line: 2 });
+        bounds.advance(&mut ip, &Direction::Left);
+        bounds.advance(&mut ip, &Direction::Left);
+        assert_eq!(ip, InstructionPtr { chr: -2, line: 2 });
+        bounds.advance(&mut ip, &Direction::Left);
+        assert_eq!(ip, InstructionPtr { chr: 2, line: 2 });
+
+        let mut ip = InstructionPtr { chr: 0, line: 0 };
+        bounds.advance(&mut ip, &Direction::Up);
+        assert_eq!(ip, InstructionPtr { chr: 0, line: 4 });
+    }
+
+    #[test]
+    fn view_elides_lines_and_columns_between_cells() {
+        let mut view = View::new(3, 1);
+        view.include(-1, 0);
+        view.include(i64::MAX, i64::MIN);
+
+        assert_eq!(
+            view.columns(),
+            vec![Some(-1), Some(0), Some(1), Some(2), None, Some(i64::MAX)]
+        );
+        assert_eq!(view.lines(), vec![Some(i64::MIN), None, Some(0)]);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::compat::Touch;
+use crate::{Direction, Event, InstructionPtr, MemPos, ParserState, StackOfStacks, Val};
+use std::collections::VecDeque;
+
+/// Changes made to the stacks by a single instruction.
+#[derive(Clone, Debug)]
+pub(crate) enum StackDelta {
+    /// Only the top of the current stack changed: it had `len` values,
+    /// the last of which were replaced by whatever the instruction pushed.
+    Top {
+        len: usize,
+        popped: Vec<Val>,
+        register: Option<Option<Val>>,
+    },
+    /// The current stack was shifted right with `}`.
+    RightShift,
+    /// The current stack was shifted left with `{`.
+    LeftShift,
+    /// The current stack was reversed with `r`.
+    Reverse,
+    /// The stack of stacks changed with `[` or `]`.
+    Full(StackOfStacks<Val>),
+}
+
+/// A cell written with `p`, along with its previous value.
+#[derive(Clone, Debug)]
+pub(crate) struct MemoryDelta {
+    pub pos: MemPos,
+    pub previous: Option<Val>,
+}
+
+/// State needed to undo a single step.
+#[derive(Clone, Debug)]
+pub(crate) struct Delta {
+    pub ip: InstructionPtr,
+    pub dir: Direction,
+    pub state: ParserState,
+    pub stack: StackDelta,
+    pub memory: Option<MemoryDelta>,
+    /// Input bytes and random directions used by the step.
+    pub events: Vec<Event>,
+    /// Lines made wider by the step in `Compat::FishPy` mode.
+    pub touched: Vec<Touch>,
+    /// Whether the output ended with a newline before the step.
+    pub newline: Option<bool>,
+}
+
+/// Bounded history of executed steps, allowing the interpreter to step backwards.
+///
+/// Output written by the program cannot be taken back. Input bytes and random
+/// directions are kept, and replayed when the undone steps are executed again.
+#[derive(Clone, Debug)]
+pub struct History {
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+    /// Events of undone steps, the next one to use last.
+    redo: Vec<Event>,
+}
+
+impl History {
+    /// Creates a history remembering at most `capacity` steps.
+    pub fn new(capacity: usize) -> Self {
+        History {
+            deltas: VecDeque::new(),
+            capacity,
+            redo: vec![],
+        }
+    }
+
+    /// Returns the number of steps that can be undone.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.redo.clear();
+    }
+
+    pub(crate) fn push(&mut self, delta: Delta) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Delta> {
+        self.deltas.pop_back()
+    }
+
+    /// Keeps the event of an undone step, to be used again when the step is redone.
+    pub(crate) fn push_redo(&mut self, event: Event) {
+        self.redo.push(event);
+    }
+
+    pub(crate) fn next_redo(&mut self) -> Option<Event> {
+        self.redo.pop()
+    }
+}
+
+/// Returns the number of values an instruction may pop from the current stack,
+/// or `None` if it changes the stacks in a way that cannot be described by pops.
+pub(crate) fn popped_values(instruction: u8) -> Option<usize> {
+    match instruction {
+        b'?' | b':' | b'~' | b'&' | b'o' | b'n' => Some(1),
+        b'.' | b'+' | b'-' | b'*' | b',' | b'%' | b'=' | b')' | b'(' | b'$' | b'g' => Some(2),
+        b'@' | b'p' => Some(3),
+        b'}' | b'{' | b'r' | b'[' | b']' => None,
+        _ => Some(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta() -> Delta {
+        Delta {
+            ip: InstructionPtr { chr: 0, line: 0 },
+            dir: Direction::Right,
+            state: ParserState::Normal,
+            stack: StackDelta::Reverse,
+            memory: None,
+            events: vec![],
+            touched: vec![],
+            newline: None,
+        }
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut h = History::new(2);
+        h.push(delta());
+        h.push(delta());
+        h.push(delta());
+
+        assert_eq!(h.len(), 2);
+        assert!(h.pop().is_some());
+        assert!(h.pop().is_some());
+        assert!(h.pop().is_none());
+    }
+
+    #[test]
+    fn history_with_zero_capacity_is_empty() {
+        let mut h = History::new(0);
+        h.push(delta());
+
+        assert!(h.is_empty());
+    }
+}
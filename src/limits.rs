@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Resource limits enforced by the interpreter, useful to run untrusted programs.
+///
+/// Every limit is disabled when set to `None`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Maximum number of instructions executed by a single run.
+    pub max_steps: Option<u64>,
+    /// Maximum wall-clock time of a single run.
+    pub max_duration: Option<Duration>,
+    /// Maximum number of values held by all the stacks combined.
+    pub max_stack_size: Option<usize>,
+    /// Maximum number of codebox cells written with `p`.
+    pub max_memory_cells: Option<usize>,
+}
+
+impl Limits {
+    /// Returns true if no limit is set.
+    pub fn is_unlimited(&self) -> bool {
+        self == &Limits::default()
+    }
+}
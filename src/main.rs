@@ -1,10 +1,157 @@
-use clap::Parser;
+mod debugger;
+mod visualize;
+
+use clap::{Parser, Subcommand};
+use std::fs::File;
+use std::io::{self, Read};
 use std::path::PathBuf;
 use std::{process, time::Duration};
 
 #[derive(Parser)]
 #[command(version, author = "marc.noirot@gmail.com", about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    program: Program,
+
+    /// define a delay in seconds between the execution of each instruction (e.g. 0.05)
+    #[arg(short = 't', long = "tick", value_name = "SECONDS", value_parser = parse_seconds)]
+    tick: Option<Duration>,
+
+    /// make every instruction cause a tick, even whitespace and skipped instructions
+    #[arg(short = 'a', long = "always-tick")]
+    always_tick: bool,
+
+    /// dump interpreter state before executing an instruction
+    #[arg(short = 'd', long = "debug")]
+    debug: bool,
+
+    /// redraw the codebox in the terminal at every tick, highlighting the current cell
+    #[arg(long = "visualize")]
+    visualize: bool,
+
+    /// seed the random number generator used by x (a random seed is used by default)
+    #[arg(long = "seed")]
+    seed: Option<u64>,
+
+    /// record input bytes and random directions to this file, for use with the replay command
+    #[arg(long = "record", value_name = "RECORDING")]
+    record: Option<PathBuf>,
+
+    /// save the interpreter state to this file when the program stops, for use with --resume-state
+    #[arg(long = "save-state", value_name = "STATE")]
+    save_state: Option<PathBuf>,
+
+    /// resume the program from a state saved with --save-state, keeping its options and step count
+    #[arg(
+        long = "resume-state",
+        value_name = "STATE",
+        conflicts_with_all = ["input", "code", "strings", "numbers", "record"]
+    )]
+    resume_state: Option<PathBuf>,
+
+    #[command(flatten)]
+    limits: LimitArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// run a program in the interactive step debugger
+    Debug {
+        #[command(flatten)]
+        program: Program,
+
+        /// read the program input from this file instead of an empty input
+        #[arg(short = 'i', long = "input")]
+        stdin: Option<PathBuf>,
+
+        /// number of instructions that can be undone with back and reverse-continue
+        #[arg(long, value_name = "STEPS", default_value_t = 100_000)]
+        history: usize,
+
+        /// number of instructions after which continue stops and returns to the prompt
+        #[arg(long, value_name = "STEPS", default_value_t = 10_000_000)]
+        continue_limit: u64,
+    },
+
+    /// run a program and print execution counts per cell and per instruction
+    Profile {
+        #[command(flatten)]
+        program: Program,
+
+        /// number of hottest cells to display
+        #[arg(long = "top", default_value_t = 10)]
+        top: usize,
+
+        /// seed the random number generator used by x
+        #[arg(long = "seed")]
+        seed: Option<u64>,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+
+    /// run a program one or more times and report which cells were executed
+    Coverage {
+        #[command(flatten)]
+        program: Program,
+
+        /// run the program once per input file, reading its input from the file
+        #[arg(short = 'i', long = "input")]
+        inputs: Vec<PathBuf>,
+
+        /// output format of the report
+        #[arg(long = "format", value_enum, default_value_t = CoverageFormat::Text)]
+        format: CoverageFormat,
+
+        /// seed the random number generator used by x
+        #[arg(long = "seed")]
+        seed: Option<u64>,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+
+    /// compare two traces written with --debug (or by fish.py) and show the first difference
+    DiffTrace {
+        /// first trace
+        #[arg(value_name = "A")]
+        a: PathBuf,
+
+        /// second trace
+        #[arg(value_name = "B")]
+        b: PathBuf,
+
+        /// compare numbers by value, so that 3 and 3.0 match,
+        /// allowing floats to differ by this relative amount (e.g. 1e-9)
+        #[arg(long = "float-tolerance", value_name = "TOLERANCE")]
+        float_tolerance: Option<f64>,
+    },
+
+    /// run a program recorded with --record, reproducing its input and random choices
+    Replay {
+        /// recording file created with --record
+        #[arg(value_name = "RECORDING")]
+        recording: PathBuf,
+
+        /// reproduce the timing of the recorded input
+        #[arg(long = "realtime")]
+        realtime: bool,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum CoverageFormat {
+    Text,
+    Json,
+    Lcov,
+}
+
+#[derive(clap::Args)]
+struct Program {
     /// set the input file to use
     #[arg(value_name = "FILE", conflicts_with = "code")]
     input: Option<PathBuf>,
@@ -21,59 +168,507 @@ struct Args {
     #[arg(short = 'v', long = "value")]
     numbers: Vec<i64>,
 
-    /// define a delay between the execution of each instruction
-    #[arg(short = 't', long = "tick")]
-    tick: Option<u64>,
+    /// what + - and * do when an integer result does not fit in 64 bits
+    /// (bignum by default when available, error otherwise)
+    #[arg(long = "overflow", value_name = "POLICY")]
+    overflow: Option<Overflow>,
 
-    /// make every instruction cause a tick, even whitespace and skipped instructions
-    #[arg(short = 'a', long = "always-tick")]
-    always_tick: bool,
+    /// how n prints numbers: python reproduces the output of the reference interpreter
+    #[arg(long = "number-format", value_name = "FORMAT")]
+    number_format: Option<NumberFormat>,
 
-    /// dump interpreter state before executing an instruction
-    #[arg(short = 'd', long = "debug")]
-    debug: bool,
+    /// make , divide integers into exact fractions instead of floats
+    #[cfg(feature = "rational")]
+    #[arg(long = "exact-division")]
+    exact_division: bool,
+
+    /// follow the semantics of another interpreter where they differ:
+    /// fishpy behaves like the reference interpreter fish.py
+    #[arg(long = "compat", value_name = "INTERPRETER")]
+    compat: Option<Compat>,
+
+    /// read the code as UTF-8, with one character per cell, and make i, o and
+    /// pushed strings use Unicode characters instead of bytes
+    #[arg(long = "unicode")]
+    unicode: bool,
+
+    /// grow the codebox to include the cells written with p outside of it,
+    /// so that the instruction pointer can reach them, at negative coordinates too
+    #[arg(long = "grow-code")]
+    grow_code: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Compat {
+    Native,
+    Fishpy,
+}
+
+impl From<Compat> for fish::Compat {
+    fn from(c: Compat) -> Self {
+        match c {
+            Compat::Native => fish::Compat::Native,
+            Compat::Fishpy => fish::Compat::FishPy,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum NumberFormat {
+    Native,
+    Python,
+}
+
+impl From<NumberFormat> for fish::NumberFormat {
+    fn from(f: NumberFormat) -> Self {
+        match f {
+            NumberFormat::Native => fish::NumberFormat::Native,
+            NumberFormat::Python => fish::NumberFormat::Python,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Overflow {
+    Error,
+    Wrap,
+    Saturate,
+    Float,
+    #[cfg(feature = "bignum")]
+    Bignum,
+}
+
+impl From<Overflow> for fish::OverflowPolicy {
+    fn from(o: Overflow) -> Self {
+        match o {
+            Overflow::Error => fish::OverflowPolicy::Error,
+            Overflow::Wrap => fish::OverflowPolicy::Wrap,
+            Overflow::Saturate => fish::OverflowPolicy::Saturate,
+            Overflow::Float => fish::OverflowPolicy::Float,
+            #[cfg(feature = "bignum")]
+            Overflow::Bignum => fish::OverflowPolicy::Bignum,
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct LimitArgs {
+    /// stop with an error after executing this many instructions
+    #[arg(long = "max-steps", value_name = "STEPS")]
+    max_steps: Option<u64>,
+
+    /// stop with an error after running for this many seconds
+    #[arg(long = "timeout", value_name = "SECONDS", value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+
+    /// stop with an error when the stacks hold more than this many values
+    #[arg(long = "max-stack", value_name = "VALUES")]
+    max_stack: Option<usize>,
+
+    /// stop with an error when more than this many cells are written with p
+    #[arg(long = "max-memory", value_name = "CELLS")]
+    max_memory: Option<usize>,
+}
+
+impl LimitArgs {
+    fn limits(&self) -> fish::Limits {
+        fish::Limits {
+            max_steps: self.max_steps,
+            max_duration: self.timeout,
+            max_stack_size: self.max_stack,
+            max_memory_cells: self.max_memory,
+        }
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds: f64 = s.parse().map_err(|_| format!("invalid duration: {}", s))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+impl Program {
+    fn load(&self) -> fish::CodeBox {
+        let mut code_box = match &self.code {
+            Some(c) => fish::CodeBox::load_from_string(c),
+            None => {
+                let input = self.input.as_ref().unwrap_or_else(|| {
+                    println!("Error: missing file name");
+                    process::exit(1)
+                });
+                fish::CodeBox::load_from_file(input).unwrap_or_else(|e| {
+                    println!("Error: {}", e);
+                    process::exit(2)
+                })
+            }
+        };
+        if self.is_fishpy() {
+            code_box.strip_hashbang();
+        }
+        if self.unicode || self.is_fishpy() {
+            code_box.decode_utf8();
+        }
+        code_box
+    }
+
+    fn is_fishpy(&self) -> bool {
+        self.compat == Some(Compat::Fishpy)
+    }
+
+    fn init<R: Read, W: io::Write>(&self, fish: &mut fish::Interpreter<R, W>) {
+        self.set_number_options(fish);
+
+        for s in &self.strings {
+            fish.push_str(s);
+        }
+
+        for n in &self.numbers {
+            fish.push_i64(*n);
+        }
+    }
+
+    fn set_number_options<R: Read, W: io::Write>(&self, fish: &mut fish::Interpreter<R, W>) {
+        if let Some(overflow) = self.overflow {
+            fish.overflow = overflow.into();
+        }
+        if let Some(format) = self.number_format {
+            fish.number_format = format.into();
+        }
+        #[cfg(feature = "rational")]
+        {
+            fish.exact_division |= self.exact_division;
+        }
+        if let Some(compat) = self.compat {
+            fish.compat = compat.into();
+        }
+        fish.unicode |= self.unicode;
+        fish.grow_code |= self.grow_code;
+    }
+}
+
+fn open_input(path: &Option<PathBuf>) -> Box<dyn Read> {
+    match path {
+        Some(path) => Box::new(File::open(path).unwrap_or_else(|e| {
+            println!("Error: {}", e);
+            process::exit(2)
+        })),
+        None => Box::new(io::empty()),
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    let code_box = match args.code {
-        Some(c) => fish::CodeBox::load_from_string(&c),
-        None => {
-            let input = args.input.unwrap_or_else(|| {
-                println!("Error: missing file name");
-                process::exit(1)
-            });
-            fish::CodeBox::load_from_file(&input).unwrap_or_else(|e| {
+    match args.command {
+        Some(Command::Debug {
+            program,
+            stdin,
+            history,
+            continue_limit,
+        }) => {
+            let code_box = program.load();
+            let mut fish = fish::Interpreter::new(open_input(&stdin), io::stdout());
+            program.init(&mut fish);
+            fish.history = Some(fish::History::new(history));
+
+            debugger::Debugger::new(fish, &code_box, continue_limit).run();
+        }
+        Some(Command::Profile {
+            program,
+            top,
+            seed,
+            limits,
+        }) => {
+            let code_box = program.load();
+            let mut fish = fish::Interpreter::new(io::stdin(), io::stdout());
+            program.init(&mut fish);
+            fish.set_seed(seed.unwrap_or_else(rand::random));
+            fish.limits = limits.limits();
+            fish.profile = Some(fish::Profile::new());
+
+            let result = fish.run(&code_box);
+            println!();
+
+            if let Some(profile) = &fish.profile {
+                eprint!("{}", profile.report(&code_box, top));
+            }
+            exit_on_error(&fish, result, &code_box);
+        }
+        Some(Command::Coverage {
+            program,
+            inputs,
+            format,
+            seed,
+            limits,
+        }) => {
+            let code_box = program.load();
+            let runs = if inputs.is_empty() {
+                vec![None]
+            } else {
+                inputs.into_iter().map(Some).collect()
+            };
+
+            let mut coverage = fish::Coverage::new();
+            for input in runs {
+                let mut fish = fish::Interpreter::new(open_input(&input), io::sink());
+                program.init(&mut fish);
+                fish.set_seed(seed.unwrap_or_else(rand::random));
+                fish.limits = limits.limits();
+                fish.coverage = Some(coverage);
+
+                if let Err(e) = fish.run(&code_box) {
+                    eprintln!("{}", fish.report(e, &code_box));
+                }
+                coverage = fish.coverage.take().unwrap_or_default();
+            }
+
+            match format {
+                CoverageFormat::Text => print!("{}", coverage.annotate(&code_box)),
+                CoverageFormat::Json => println!("{}", coverage.to_json(&code_box)),
+                CoverageFormat::Lcov => {
+                    let source = program
+                        .input
+                        .as_ref()
+                        .map_or("-".to_string(), |p| p.display().to_string());
+                    print!("{}", coverage.to_lcov(&source, &code_box))
+                }
+            }
+        }
+        Some(Command::Replay {
+            recording,
+            realtime,
+        }) => {
+            let recording: fish::Recording = File::open(&recording)
+                .map_err(|e| e.to_string())
+                .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+                .unwrap_or_else(|e| {
+                    println!("Error: {}", e);
+                    process::exit(2)
+                });
+
+            let code_box = recording.code_box();
+            let mut fish = fish::Interpreter::new(io::empty(), io::stdout());
+            fish.unicode = recording.unicode;
+            fish.always_tick = recording.always_tick;
+            fish.limits = recording.limits.clone();
+            fish.overflow = recording.overflow;
+            fish.number_format = recording.number_format;
+            fish.compat = recording.compat;
+            fish.grow_code = recording.grow_code;
+            #[cfg(feature = "rational")]
+            {
+                fish.exact_division = recording.exact_division;
+            }
+            #[cfg(not(feature = "rational"))]
+            if recording.exact_division {
+                println!("Error: exact division requires the rational feature");
+                process::exit(2)
+            }
+            for v in &recording.initial_stack {
+                fish.stack.top_mut().push(v.clone());
+            }
+            let mut replay = fish::Replay::new(&recording);
+            replay.realtime = realtime;
+            fish.replay = Some(replay);
+
+            let result = fish.run(&code_box);
+            if fish.compat == fish::Compat::FishPy {
+                exit_fishpy(&fish, result);
+                return;
+            }
+            exit_on_error(&fish, result, &code_box);
+            println!();
+        }
+        Some(Command::DiffTrace {
+            a,
+            b,
+            float_tolerance,
+        }) => {
+            let open = |path: &PathBuf| {
+                File::open(path)
+                    .map(io::BufReader::new)
+                    .unwrap_or_else(|e| {
+                        println!("Error: {}: {}", path.display(), e);
+                        process::exit(2)
+                    })
+            };
+            match fish::diff_traces(open(&a), open(&b), float_tolerance) {
+                Ok(None) => println!("traces are identical"),
+                Ok(Some(divergence)) => {
+                    print!("{}", divergence);
+                    process::exit(1);
+                }
+                Err(e) => {
+                    println!("Error: {}", e);
+                    process::exit(2)
+                }
+            }
+        }
+        None => run(args),
+    }
+}
+
+impl Args {
+    fn configure<R: Read, W: io::Write>(
+        &self,
+        fish: &mut fish::Interpreter<R, W>,
+        code_box: &fish::CodeBox,
+        snapshot: Option<&fish::Snapshot>,
+    ) {
+        match snapshot {
+            Some(snapshot) => {
+                #[cfg(not(feature = "rational"))]
+                if snapshot.exact_division {
+                    println!("Error: exact division requires the rational feature");
+                    process::exit(2)
+                }
+                // options given on the command line add to the saved ones
+                fish.restore(snapshot);
+                self.program.set_number_options(fish);
+                fish.unicode |= snapshot.unicode;
+            }
+            None => self.program.init(fish),
+        }
+
+        fish.trace = self.debug;
+        // keep the random number generator of a resumed state unless a seed is given
+        if self.seed.is_some() || fish.seed().is_none() {
+            fish.set_seed(self.seed.unwrap_or_else(rand::random));
+        }
+        fish.limits = self.limits.limits();
+
+        fish.tick = self.tick;
+        fish.always_tick = self.always_tick;
+
+        if self.record.is_some() {
+            let initial_stack = fish.stack.top().values.iter().cloned().collect();
+            let mut recording = fish::Recording::new(code_box, initial_stack);
+            recording.always_tick = fish.always_tick;
+            recording.limits = fish.limits.clone();
+            recording.overflow = fish.overflow;
+            recording.number_format = fish.number_format;
+            recording.compat = fish.compat;
+            recording.grow_code = fish.grow_code;
+            #[cfg(feature = "rational")]
+            {
+                recording.exact_division = fish.exact_division;
+            }
+            fish.recording = Some(recording);
+        }
+    }
+
+    fn save_recording<R: Read, W: io::Write>(&self, fish: &fish::Interpreter<R, W>) {
+        if let (Some(path), Some(recording)) = (&self.record, &fish.recording) {
+            let saved = File::create(path)
+                .map_err(|e| e.to_string())
+                .and_then(|f| serde_json::to_writer(f, recording).map_err(|e| e.to_string()));
+            if let Err(e) = saved {
+                eprintln!("Error: cannot save recording: {}", e);
+            }
+        }
+    }
+
+    fn load_state(&self) -> Option<fish::Snapshot> {
+        let path = self.resume_state.as_ref()?;
+        let snapshot = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
                 println!("Error: {}", e);
                 process::exit(2)
-            })
+            });
+        Some(snapshot)
+    }
+
+    fn save_state<R: Read, W: io::Write>(
+        &self,
+        fish: &fish::Interpreter<R, W>,
+        code_box: &fish::CodeBox,
+    ) {
+        if let Some(path) = &self.save_state {
+            let snapshot = fish.snapshot(code_box);
+            let saved = File::create(path)
+                .map_err(|e| e.to_string())
+                .and_then(|f| serde_json::to_writer(f, &snapshot).map_err(|e| e.to_string()));
+            if let Err(e) = saved {
+                eprintln!("Error: cannot save state: {}", e);
+            }
         }
+    }
+}
+
+fn run(args: Args) {
+    let snapshot = args.load_state();
+    let code_box = match &snapshot {
+        Some(snapshot) => snapshot.code_box(),
+        None => args.program.load(),
     };
 
+    if args.visualize {
+        let output = visualize::Output::default();
+        let mut fish = fish::Interpreter::new(io::stdin(), output.clone());
+        args.configure(&mut fish, &code_box, snapshot.as_ref());
+        if snapshot.is_none() {
+            fish.reset();
+        }
+
+        // the visualizer handles ticks itself, after each redraw
+        fish.tick = None;
+        let tick = args.tick.unwrap_or(visualize::DEFAULT_TICK);
+
+        let result = visualize::run(&mut fish, &code_box, &output, tick, args.always_tick);
+        args.save_recording(&fish);
+        args.save_state(&fish, &code_box);
+        exit_on_error(&fish, result, &code_box);
+        return;
+    }
+
     let input = std::io::stdin();
     let output = std::io::stdout();
 
     let mut fish = fish::Interpreter::new(input, output);
+    args.configure(&mut fish, &code_box, snapshot.as_ref());
 
-    for s in &args.strings {
-        fish.push_str(s);
-    }
-
-    for n in args.numbers {
-        fish.push_i64(n);
+    let result = match snapshot {
+        Some(_) => fish.resume(&code_box),
+        None => fish.run(&code_box),
+    };
+    args.save_recording(&fish);
+    args.save_state(&fish, &code_box);
+    if fish.compat == fish::Compat::FishPy {
+        exit_fishpy(&fish, result);
+        return;
     }
+    exit_on_error(&fish, result, &code_box);
 
-    fish.trace = args.debug;
+    println!();
+}
 
-    if let Some(seconds) = args.tick {
-        fish.tick = Some(Duration::from_secs(seconds));
+/// Ends the run like fish.py: the newline ending the output and
+/// the error message are written to the error output.
+fn exit_fishpy<R: Read, W: io::Write>(fish: &fish::Interpreter<R, W>, result: fish::Result<()>) {
+    // keep the order of both outputs
+    let _ = io::Write::flush(&mut io::stdout());
+    let newline = match fish.output_ends_with_newline() {
+        Some(false) => "\n",
+        _ => "",
+    };
+    match result {
+        Ok(()) => eprint!("{}", newline),
+        Err(_) => {
+            eprintln!("{}something smells fishy...", newline);
+            process::exit(3);
+        }
     }
+}
 
-    if fish.run(&code_box).is_err() {
+fn exit_on_error<R: Read, W: io::Write>(
+    fish: &fish::Interpreter<R, W>,
+    result: fish::Result<()>,
+    code_box: &fish::CodeBox,
+) {
+    if let Err(e) = result {
         println!("something smells fishy...");
+        eprintln!("{}", fish.report(e, code_box));
         process::exit(3);
     }
-
-    println!();
 }
@@ -0,0 +1,170 @@
+/// An instruction decoded from a cell.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Op {
+    /// A space, which does nothing.
+    Nop,
+    SingleQuote,
+    DoubleQuote,
+    Right,
+    Left,
+    Up,
+    Down,
+    /// `/`
+    Slash,
+    /// `\`
+    Backslash,
+    /// `|`
+    VerticalMirror,
+    /// `_`
+    HorizontalMirror,
+    /// `#`
+    FullMirror,
+    Random,
+    Trampoline,
+    Conditional,
+    Jump,
+    /// A hexadecimal digit, with its value.
+    Literal(u8),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Equals,
+    Greater,
+    Less,
+    Dup,
+    Drop,
+    Swap,
+    Swap2,
+    RightShift,
+    LeftShift,
+    Reverse,
+    Length,
+    NewStack,
+    RemoveStack,
+    CharOutput,
+    NumOutput,
+    Input,
+    Register,
+    Get,
+    Put,
+    End,
+    /// Any other byte.
+    Invalid(u8),
+}
+
+impl Op {
+    pub fn decode(instruction: u8) -> Op {
+        match instruction {
+            b' ' => Op::Nop,
+            b'\'' => Op::SingleQuote,
+            b'"' => Op::DoubleQuote,
+            b'>' => Op::Right,
+            b'<' => Op::Left,
+            b'^' => Op::Up,
+            b'v' => Op::Down,
+            b'/' => Op::Slash,
+            b'\\' => Op::Backslash,
+            b'|' => Op::VerticalMirror,
+            b'_' => Op::HorizontalMirror,
+            b'#' => Op::FullMirror,
+            b'x' => Op::Random,
+            b'!' => Op::Trampoline,
+            b'?' => Op::Conditional,
+            b'.' => Op::Jump,
+            b'0'..=b'9' => Op::Literal(instruction - b'0'),
+            b'a'..=b'f' => Op::Literal(instruction - b'a' + 10),
+            b'+' => Op::Add,
+            b'-' => Op::Sub,
+            b'*' => Op::Mul,
+            b',' => Op::Div,
+            b'%' => Op::Rem,
+            b'=' => Op::Equals,
+            b')' => Op::Greater,
+            b'(' => Op::Less,
+            b':' => Op::Dup,
+            b'~' => Op::Drop,
+            b'$' => Op::Swap,
+            b'@' => Op::Swap2,
+            b'}' => Op::RightShift,
+            b'{' => Op::LeftShift,
+            b'r' => Op::Reverse,
+            b'l' => Op::Length,
+            b'[' => Op::NewStack,
+            b']' => Op::RemoveStack,
+            b'o' => Op::CharOutput,
+            b'n' => Op::NumOutput,
+            b'i' => Op::Input,
+            b'&' => Op::Register,
+            b'g' => Op::Get,
+            b'p' => Op::Put,
+            b';' => Op::End,
+            _ => Op::Invalid(instruction),
+        }
+    }
+
+    /// Returns the byte this instruction was decoded from.
+    pub fn byte(self) -> u8 {
+        match self {
+            Op::Nop => b' ',
+            Op::SingleQuote => b'\'',
+            Op::DoubleQuote => b'"',
+            Op::Right => b'>',
+            Op::Left => b'<',
+            Op::Up => b'^',
+            Op::Down => b'v',
+            Op::Slash => b'/',
+            Op::Backslash => b'\\',
+            Op::VerticalMirror => b'|',
+            Op::HorizontalMirror => b'_',
+            Op::FullMirror => b'#',
+            Op::Random => b'x',
+            Op::Trampoline => b'!',
+            Op::Conditional => b'?',
+            Op::Jump => b'.',
+            Op::Literal(v @ 0..=9) => b'0' + v,
+            Op::Literal(v) => b'a' + v - 10,
+            Op::Add => b'+',
+            Op::Sub => b'-',
+            Op::Mul => b'*',
+            Op::Div => b',',
+            Op::Rem => b'%',
+            Op::Equals => b'=',
+            Op::Greater => b')',
+            Op::Less => b'(',
+            Op::Dup => b':',
+            Op::Drop => b'~',
+            Op::Swap => b'$',
+            Op::Swap2 => b'@',
+            Op::RightShift => b'}',
+            Op::LeftShift => b'{',
+            Op::Reverse => b'r',
+            Op::Length => b'l',
+            Op::NewStack => b'[',
+            Op::RemoveStack => b']',
+            Op::CharOutput => b'o',
+            Op::NumOutput => b'n',
+            Op::Input => b'i',
+            Op::Register => b'&',
+            Op::Get => b'g',
+            Op::Put => b'p',
+            Op::End => b';',
+            Op::Invalid(b) => b,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoding_round_trips() {
+        for b in 0..=255 {
+            assert_eq!(Op::decode(b).byte(), b);
+        }
+        assert_eq!(Op::decode(b'e'), Op::Literal(14));
+        assert_eq!(Op::decode(b'A'), Op::Invalid(b'A'));
+    }
+}
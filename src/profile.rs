@@ -0,0 +1,222 @@
+use crate::{CodeBox, InstructionPtr, View};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Characters used to shade cells, from never executed to hottest.
+const SHADES: &[u8] = b" .:-=+*#%@";
+
+/// Execution counts collected while running a program.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    /// Number of executions of each cell, keyed by `(chr, line)`.
+    pub cells: HashMap<(i64, i64), u64>,
+    /// Number of executions of each instruction, excluding characters pushed in string mode.
+    pub instructions: HashMap<u8, u64>,
+    /// Number of characters pushed in string mode.
+    pub string_steps: u64,
+    /// Total number of steps.
+    pub steps: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, ip: &InstructionPtr, instruction: u8, quoted: bool) {
+        *self.cells.entry((ip.chr, ip.line)).or_insert(0) += 1;
+        if quoted {
+            self.string_steps += 1;
+        } else {
+            *self.instructions.entry(instruction).or_insert(0) += 1;
+        }
+        self.steps += 1;
+    }
+
+    /// Returns the `n` most executed cells, hottest first.
+    pub fn hottest_cells(&self, n: usize) -> Vec<((i64, i64), u64)> {
+        let mut cells: Vec<_> = self
+            .cells
+            .iter()
+            .map(|(&pos, &count)| (pos, count))
+            .collect();
+        cells.sort_by(|(pa, a), (pb, b)| b.cmp(a).then((pa.1, pa.0).cmp(&(pb.1, pb.0))));
+        cells.truncate(n);
+        cells
+    }
+
+    /// Returns instructions sorted by decreasing execution count.
+    pub fn instruction_frequencies(&self) -> Vec<(u8, u64)> {
+        let mut instructions: Vec<_> = self.instructions.iter().map(|(&i, &c)| (i, c)).collect();
+        instructions.sort_by(|(ia, a), (ib, b)| b.cmp(a).then(ia.cmp(ib)));
+        instructions
+    }
+
+    /// Renders the codebox next to a map of cells shaded by execution count.
+    ///
+    /// Cells executed outside of the code when it grows are shown too,
+    /// the lines and columns between them and the code are elided with `…`.
+    pub fn heatmap(&self, code: &CodeBox) -> String {
+        let mut view = View::new(code.width, code.height);
+        for &(x, y) in self.cells.keys() {
+            view.include(x, y);
+        }
+        let columns = view.columns();
+
+        let max = self.cells.values().copied().max().unwrap_or(0);
+        let mut out = String::new();
+        for y in view.lines() {
+            let y = match y {
+                Some(y) => y,
+                None => {
+                    out.push_str("…\n");
+                    continue;
+                }
+            };
+            let line: String = columns
+                .iter()
+                // negative coordinates become too large
+                .map(|x| match x.map(|x| code.get(x as usize, y as usize)) {
+                    Some(Some(c)) if c.is_ascii_graphic() => c as char,
+                    Some(Some(b' ')) | Some(None) => ' ',
+                    Some(Some(_)) => '?',
+                    None => '…',
+                })
+                .collect();
+            let heat: String = columns
+                .iter()
+                .map(|x| match x {
+                    Some(x) => shade(self.cells.get(&(*x, y)).copied().unwrap_or(0), max),
+                    None => '…',
+                })
+                .collect();
+            let _ = writeln!(out, "{} | {}", line, heat.trim_end());
+        }
+        out
+    }
+
+    /// Renders a complete report with the heatmap and the hottest cells and instructions.
+    pub fn report(&self, code: &CodeBox, top: usize) -> String {
+        let mut out = self.heatmap(code);
+        let percent = |count: u64| 100.0 * count as f64 / self.steps.max(1) as f64;
+
+        let _ = writeln!(out, "\ntotal steps: {}", self.steps);
+
+        let _ = writeln!(out, "\nhottest cells:");
+        for ((x, y), count) in self.hottest_cells(top) {
+            // cells outside of the codebox are blank
+            let c = code.get(x as usize, y as usize).map_or(' ', printable);
+            let _ = writeln!(
+                out,
+                "  ({:>3}, {:>3}) '{}' {:>12} {:>6.2}%",
+                x,
+                y,
+                c,
+                count,
+                percent(count)
+            );
+        }
+
+        let _ = writeln!(out, "\ninstructions:");
+        for (i, count) in self.instruction_frequencies() {
+            let _ = writeln!(
+                out,
+                "  '{}' {:>12} {:>6.2}%",
+                printable(i),
+                count,
+                percent(count)
+            );
+        }
+        if self.string_steps > 0 {
+            let _ = writeln!(
+                out,
+                "  string {:>9} {:>6.2}%",
+                self.string_steps,
+                percent(self.string_steps)
+            );
+        }
+
+        out
+    }
+}
+
+/// Returns the shade character of a cell, on a logarithmic scale.
+fn shade(count: u64, max: u64) -> char {
+    if count == 0 {
+        return SHADES[0] as char;
+    }
+    let ratio = ((count + 1) as f64).ln() / ((max + 1) as f64).ln();
+    let idx = 1 + (ratio * (SHADES.len() - 2) as f64).round() as usize;
+    SHADES[idx.min(SHADES.len() - 1)] as char
+}
+
+fn printable(c: u8) -> char {
+    if c.is_ascii_graphic() || c == b' ' {
+        c as char
+    } else {
+        '?'
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(chr: i64, line: i64) -> InstructionPtr {
+        InstructionPtr { chr, line }
+    }
+
+    #[test]
+    fn record_works() {
+        let mut p = Profile::new();
+        p.record(&ip(0, 0), b'1', false);
+        p.record(&ip(1, 0), b'a', true);
+        p.record(&ip(0, 0), b'1', false);
+
+        assert_eq!(p.steps, 3);
+        assert_eq!(p.string_steps, 1);
+        assert_eq!(p.cells[&(0, 0)], 2);
+        assert_eq!(p.instructions[&b'1'], 2);
+        assert!(!p.instructions.contains_key(&b'a'));
+    }
+
+    #[test]
+    fn hottest_cells_are_sorted() {
+        let mut p = Profile::new();
+        p.record(&ip(0, 0), b'1', false);
+        p.record(&ip(2, 0), b'+', false);
+        p.record(&ip(2, 0), b'+', false);
+        p.record(&ip(1, 0), b'2', false);
+
+        assert_eq!(p.hottest_cells(2), vec![((2, 0), 2), ((0, 0), 1)]);
+    }
+
+    #[test]
+    fn shade_works() {
+        assert_eq!(shade(0, 100), ' ');
+        assert_eq!(shade(100, 100), '@');
+        assert_ne!(shade(1, 100), ' ');
+        assert_ne!(shade(1, 100), '@');
+    }
+
+    #[test]
+    fn heatmap_works() {
+        let code = CodeBox::load_from_string("1n;");
+        let mut p = Profile::new();
+        p.record(&ip(0, 0), b'1', false);
+        p.record(&ip(1, 0), b'n', false);
+
+        assert_eq!(p.heatmap(&code), "1n; | @@\n");
+    }
+
+    #[test]
+    fn heatmap_shows_cells_around_the_code() {
+        let code = CodeBox::load_from_string("1n;");
+        let mut p = Profile::new();
+        p.record(&ip(-1, 0), b'1', false);
+        p.record(&ip(0, 1), b'n', false);
+        p.record(&ip(i64::MAX, 1), b' ', false);
+
+        assert_eq!(p.heatmap(&code), " 1n;…  | @   …\n    …  |  @  …@\n");
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{
+    CodeBox, Compat, Direction, Limits, NumberFormat, OverflowPolicy, Result, RuntimeError, Val,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::{thread, time::Duration, time::Instant};
+
+/// A nondeterministic event that happened during a run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A byte read by `i`, `None` when the end of the input was reached.
+    Input {
+        step: u64,
+        byte: Option<u8>,
+        elapsed_ms: u64,
+    },
+    /// A direction chosen by `x`.
+    Random {
+        step: u64,
+        dir: Direction,
+        elapsed_ms: u64,
+    },
+}
+
+impl Event {
+    pub fn step(&self) -> u64 {
+        match *self {
+            Event::Input { step, .. } | Event::Random { step, .. } => step,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match *self {
+            Event::Input { elapsed_ms, .. } | Event::Random { elapsed_ms, .. } => {
+                Duration::from_millis(elapsed_ms)
+            }
+        }
+    }
+}
+
+/// Everything needed to reproduce a run: the code, the initial stack, the options
+/// of the interpreter, and the nondeterministic events in the order they happened.
+///
+/// Options missing from older recordings take their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Recording {
+    /// Cells of each line of code, see `CodeBox::is_unicode`.
+    pub code: Vec<Vec<u32>>,
+    /// Whether the code was decoded as UTF-8, see `CodeBox::decode_utf8`.
+    #[serde(default)]
+    pub unicode: bool,
+    /// See `Interpreter::always_tick`.
+    #[serde(default)]
+    pub always_tick: bool,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+    /// See `Interpreter::exact_division`, only available with the `rational` feature.
+    #[serde(default)]
+    pub exact_division: bool,
+    #[serde(default)]
+    pub number_format: NumberFormat,
+    #[serde(default)]
+    pub compat: Compat,
+    /// See `Interpreter::grow_code`.
+    #[serde(default)]
+    pub grow_code: bool,
+    pub initial_stack: Vec<Val>,
+    pub events: Vec<Event>,
+
+    #[serde(skip, default = "Instant::now")]
+    start: Instant,
+}
+
+impl Recording {
+    /// Starts a new recording for the given code and initial stack.
+    pub fn new(code: &CodeBox, initial_stack: Vec<Val>) -> Self {
+        Recording {
+            code: code.cells(),
+            unicode: code.is_unicode(),
+            always_tick: false,
+            limits: Limits::default(),
+            overflow: OverflowPolicy::default(),
+            exact_division: false,
+            number_format: NumberFormat::default(),
+            compat: Compat::default(),
+            grow_code: false,
+            initial_stack,
+            events: vec![],
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the recorded code.
+    pub fn code_box(&self) -> CodeBox {
+        CodeBox::from_cells(&self.code, self.unicode)
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    pub(crate) fn record_input(&mut self, step: u64, byte: Option<u8>) {
+        let elapsed_ms = self.elapsed_ms();
+        self.events.push(Event::Input {
+            step,
+            byte,
+            elapsed_ms,
+        });
+    }
+
+    pub(crate) fn record_random(&mut self, step: u64, dir: Direction) {
+        let elapsed_ms = self.elapsed_ms();
+        self.events.push(Event::Random {
+            step,
+            dir,
+            elapsed_ms,
+        });
+    }
+}
+
+/// Source of recorded events, used instead of the input and the random
+/// number generator when replaying a run.
+#[derive(Clone, Debug)]
+pub struct Replay {
+    events: VecDeque<Event>,
+    /// Wait for events to happen at the same time as in the recorded run.
+    pub realtime: bool,
+    start: Instant,
+}
+
+impl Replay {
+    pub fn new(recording: &Recording) -> Self {
+        Replay {
+            events: recording.events.iter().cloned().collect(),
+            realtime: false,
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the number of events left to replay.
+    pub fn remaining(&self) -> usize {
+        self.events.len()
+    }
+
+    fn next(&mut self, step: u64) -> Result<Event> {
+        let event = self
+            .events
+            .pop_front()
+            .ok_or(RuntimeError::ReplayDiverged)?;
+        if event.step() != step {
+            return Err(RuntimeError::ReplayDiverged);
+        }
+
+        if self.realtime {
+            if let Some(wait) = event.elapsed().checked_sub(self.start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+
+        Ok(event)
+    }
+
+    pub(crate) fn next_input(&mut self, step: u64) -> Result<Option<u8>> {
+        match self.next(step)? {
+            Event::Input { byte, .. } => Ok(byte),
+            _ => Err(RuntimeError::ReplayDiverged),
+        }
+    }
+
+    pub(crate) fn next_random(&mut self, step: u64) -> Result<Direction> {
+        match self.next(step)? {
+            Event::Random { dir, .. } => Ok(dir),
+            _ => Err(RuntimeError::ReplayDiverged),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_roundtrip_works() {
+        let code = CodeBox::load_from_string("i\u{e9}x\n;");
+        let mut r = Recording::new(&code, vec![Val::Int(5)]);
+        r.always_tick = true;
+        r.limits.max_steps = Some(100);
+        r.overflow = OverflowPolicy::Wrap;
+        r.exact_division = true;
+        r.number_format = NumberFormat::Python;
+        r.compat = Compat::FishPy;
+        r.grow_code = true;
+        r.record_input(0, Some(b'a'));
+        r.record_random(2, Direction::Up);
+
+        let json = serde_json::to_string(&r).unwrap();
+        let r2: Recording = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(
+            r2.code_box().cells(),
+            vec![vec![0x69, 0xc3, 0xa9, 0x78], vec![0x3b]]
+        );
+        assert!(r2.always_tick);
+        assert_eq!(r2.limits, r.limits);
+        assert_eq!(r2.overflow, OverflowPolicy::Wrap);
+        assert!(r2.exact_division);
+        assert_eq!(r2.number_format, NumberFormat::Python);
+        assert_eq!(r2.compat, Compat::FishPy);
+        assert!(r2.grow_code);
+        assert_eq!(r2.initial_stack, vec![Val::Int(5)]);
+        assert_eq!(r2.events, r.events);
+    }
+
+    #[test]
+    fn missing_options_take_default_values() {
+        let json = r#"{"code":[[49,110,59]],"initial_stack":[],"events":[]}"#;
+        let r: Recording = serde_json::from_str(json).unwrap();
+
+        assert!(!r.unicode);
+        assert!(!r.always_tick);
+        assert!(r.limits.is_unlimited());
+        assert_eq!(r.overflow, OverflowPolicy::default());
+        assert!(!r.exact_division);
+        assert_eq!(r.number_format, NumberFormat::Native);
+        assert_eq!(r.compat, Compat::Native);
+        assert!(!r.grow_code);
+    }
+
+    #[test]
+    fn replay_checks_event_order() {
+        let code = CodeBox::load_from_string("ix");
+        let mut r = Recording::new(&code, vec![]);
+        r.record_input(0, None);
+        r.record_random(1, Direction::Left);
+
+        let mut replay = Replay::new(&r);
+        assert_eq!(replay.next_random(0), Err(RuntimeError::ReplayDiverged));
+
+        let mut replay = Replay::new(&r);
+        assert_eq!(replay.next_input(0), Ok(None));
+        assert_eq!(replay.next_random(1), Ok(Direction::Left));
+        assert_eq!(replay.next_random(2), Err(RuntimeError::ReplayDiverged));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::grid::Bounds;
+use crate::{Direction, InstructionPtr, RuntimeError, Val};
+use std::{cmp, fmt, ops::RangeInclusive};
+
+/// Number of lines displayed above and below the failing line.
+const CONTEXT_LINES: i64 = 2;
+
+/// Number of cells displayed on each side of the failing cell.
+const CONTEXT_COLUMNS: i64 = 30;
+
+/// Number of values displayed from the top of the stack.
+const STACK_VALUES: usize = 16;
+
+/// Snapshot of the interpreter state at the point where a runtime error occurred.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorReport {
+    pub error: RuntimeError,
+    pub ip: InstructionPtr,
+    pub dir: Direction,
+    pub instruction: Option<u8>,
+    pub steps: u64,
+    pub stack: Vec<Val>,
+    pub register: Option<Val>,
+
+    /// Lines of the codebox surrounding the failing cell,
+    /// as seen by the interpreter (including cells written with `p`).
+    pub excerpt: Vec<ExcerptLine>,
+}
+
+/// A line of code displayed in an error report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExcerptLine {
+    pub line: i64,
+    pub first_chr: i64,
+    pub cells: Vec<u8>,
+}
+
+/// Returns the range of lines and columns to capture around the given position,
+/// within the given bounds.
+pub(crate) fn excerpt_window(
+    ip: &InstructionPtr,
+    bounds: &Bounds,
+) -> (RangeInclusive<i64>, RangeInclusive<i64>) {
+    let window = |pos: i64, context: i64, min: i64, max: i64| {
+        cmp::max(pos.saturating_sub(context), min)..=cmp::min(pos.saturating_add(context), max)
+    };
+    let lines = window(ip.line, CONTEXT_LINES, bounds.min_y, bounds.max_y);
+    let chrs = window(ip.chr, CONTEXT_COLUMNS, bounds.min_x, bounds.max_x);
+    (lines, chrs)
+}
+
+fn printable(c: u8) -> char {
+    if c.is_ascii_graphic() || c == b' ' {
+        c as char
+    } else {
+        '?'
+    }
+}
+
+fn dir_name(dir: &Direction) -> &'static str {
+    match dir {
+        Direction::Right => "right",
+        Direction::Left => "left",
+        Direction::Up => "up",
+        Direction::Down => "down",
+    }
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "error: {}", self.error)?;
+        write!(
+            f,
+            "  at ({}, {}) moving {}",
+            self.ip.chr,
+            self.ip.line,
+            dir_name(&self.dir)
+        )?;
+        if let Some(c) = self.instruction {
+            write!(f, ", instruction '{}' (0x{:02x})", printable(c), c)?;
+        }
+        writeln!(f, ", after {} steps", self.steps)?;
+
+        if !self.excerpt.is_empty() {
+            let gutter = self
+                .excerpt
+                .iter()
+                .map(|l| l.line.to_string().len())
+                .max()
+                .unwrap_or(1);
+
+            writeln!(f, "{:>w$} |", "", w = gutter)?;
+            for l in &self.excerpt {
+                let text: String = l.cells.iter().map(|&c| printable(c)).collect();
+                writeln!(f, "{:>w$} | {}", l.line, text.trim_end(), w = gutter)?;
+                if l.line == self.ip.line && self.ip.chr >= l.first_chr {
+                    let offset = (self.ip.chr - l.first_chr) as usize;
+                    writeln!(f, "{:>w$} | {:>o$}^", "", "", w = gutter, o = offset)?;
+                }
+            }
+            writeln!(f, "{:>w$} |", "", w = gutter)?;
+        }
+
+        let hidden = self.stack.len().saturating_sub(STACK_VALUES);
+        let mut stack = self.stack[hidden..]
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>();
+        if hidden > 0 {
+            stack.insert(0, format!("… ({} more)", hidden));
+        }
+        writeln!(f, "stack: [{}]", stack.join(", "))?;
+        match &self.register {
+            Some(v) => write!(f, "register: {}", v),
+            None => write!(f, "register: empty"),
+        }
+    }
+}
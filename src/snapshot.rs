@@ -0,0 +1,118 @@
+use crate::{
+    CodeBox, Compat, Direction, InstructionPtr, MemPos, NumberFormat, OverflowPolicy, ParserState,
+    StackOfStacks, Val,
+};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+/// Complete state of an interpreter, from which a run can be resumed.
+///
+/// The position in the input stream is not part of the state:
+/// a resumed run reads its input from the beginning of the new input.
+/// Neither are limits and ticks, which apply to each run.
+///
+/// Fields missing from older snapshots take their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Cells of each line of code, see `CodeBox::is_unicode`.
+    pub code: Vec<Vec<u32>>,
+    /// Whether the code was decoded as UTF-8, see `CodeBox::decode_utf8`.
+    #[serde(default)]
+    pub unicode: bool,
+    pub ip: InstructionPtr,
+    pub dir: Direction,
+    pub state: ParserState,
+    pub stack: StackOfStacks<Val>,
+    /// Cells written with `p`, sorted by position.
+    pub memory: Vec<(MemPos, Val)>,
+    pub steps: u64,
+    pub seed: Option<u64>,
+    /// State of the random number generator, only available when it was seeded.
+    pub rng: Option<ChaCha12Rng>,
+    /// See `Interpreter::output_ends_with_newline`.
+    #[serde(default)]
+    pub newline: Option<bool>,
+    /// Width of each line having cells in `Compat::FishPy` mode, sorted by line,
+    /// once the run started.
+    #[serde(default)]
+    pub line_widths: Option<Vec<(usize, usize)>>,
+    /// Last line in `Compat::FishPy` mode, see `line_widths`.
+    #[serde(default)]
+    pub last_line: usize,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+    /// See `Interpreter::exact_division`, only available with the `rational` feature.
+    #[serde(default)]
+    pub exact_division: bool,
+    #[serde(default)]
+    pub number_format: NumberFormat,
+    #[serde(default)]
+    pub compat: Compat,
+    /// See `Interpreter::grow_code`.
+    #[serde(default)]
+    pub grow_code: bool,
+}
+
+impl Snapshot {
+    /// Returns the saved code.
+    pub fn code_box(&self) -> CodeBox {
+        CodeBox::from_cells(&self.code, self.unicode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn snapshot_roundtrip_works() {
+        let mut stack = StackOfStacks::new();
+        stack.top_mut().push(Val::Float(1.5));
+        stack.push_stack(0).unwrap();
+        stack.top_mut().register = Some(Val::Byte(b'a'));
+
+        let s = Snapshot {
+            code: vec![vec![0x31, 0x6e, 0xe9, 0x3b]],
+            unicode: false,
+            ip: InstructionPtr { chr: 1, line: 0 },
+            dir: Direction::Left,
+            state: ParserState::SingleQuoted,
+            stack,
+            memory: vec![(MemPos { x: -1, y: 2 }, Val::Int(42))],
+            steps: 7,
+            seed: Some(5),
+            rng: Some(ChaCha12Rng::seed_from_u64(5)),
+            newline: Some(false),
+            line_widths: Some(vec![(0, 4), (3, 1)]),
+            last_line: 3,
+            overflow: OverflowPolicy::Wrap,
+            exact_division: false,
+            number_format: NumberFormat::Python,
+            compat: Compat::FishPy,
+            grow_code: true,
+        };
+
+        let json = serde_json::to_string(&s).unwrap();
+        let s2: Snapshot = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(s2.code_box().cells(), s.code);
+        assert_eq!(s2.ip, s.ip);
+        assert_eq!(s2.dir, s.dir);
+        assert_eq!(s2.stack.initial_stack.values, vec![Val::Float(1.5)]);
+        assert_eq!(
+            s2.stack.additional_stacks[0].register,
+            Some(Val::Byte(b'a'))
+        );
+        assert_eq!(s2.memory, s.memory);
+        assert_eq!(s2.steps, 7);
+        assert_eq!(s2.rng, s.rng);
+        assert_eq!(s2.newline, Some(false));
+        assert_eq!(s2.line_widths, s.line_widths);
+        assert_eq!(s2.last_line, 3);
+        assert_eq!(s2.overflow, OverflowPolicy::Wrap);
+        assert_eq!(s2.number_format, NumberFormat::Python);
+        assert_eq!(s2.compat, Compat::FishPy);
+        assert!(s2.grow_code);
+    }
+}
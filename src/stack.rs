@@ -1,10 +1,16 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
 #[derive(PartialEq, Debug)]
 pub enum Error {
     StackUnderflow,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Stack<T> {
-    pub values: Vec<T>,
+    /// Values from the bottom to the top of the stack, kept in a deque so that
+    /// `rshift` and `lshift` take constant time.
+    pub values: VecDeque<T>,
     pub register: Option<T>,
 }
 
@@ -23,7 +29,7 @@ where
 {
     pub fn new() -> Self {
         Stack {
-            values: Vec::new(),
+            values: VecDeque::new(),
             register: None,
         }
     }
@@ -37,11 +43,11 @@ where
     }
 
     pub fn push(&mut self, val: T) {
-        self.values.push(val);
+        self.values.push_back(val);
     }
 
     pub fn pop(&mut self) -> Option<T> {
-        self.values.pop()
+        self.values.pop_back()
     }
 
     pub fn switch_register(&mut self) -> Result<(), Error> {
@@ -54,8 +60,8 @@ where
     }
 
     pub fn dup(&mut self) -> Result<(), Error> {
-        let v = self.values.last().ok_or(Error::StackUnderflow)?.clone();
-        self.values.push(v);
+        let v = self.values.back().ok_or(Error::StackUnderflow)?.clone();
+        self.values.push_back(v);
         Ok(())
     }
 
@@ -91,23 +97,23 @@ where
     }
 
     pub fn rshift(&mut self) {
-        let e = self.values.pop();
-        if let Some(e) = e {
-            self.values.insert(0, e);
+        if !self.values.is_empty() {
+            self.values.rotate_right(1);
         }
     }
 
     pub fn lshift(&mut self) {
-        match self.values.len() {
-            0 | 1 => {}
-            _ => {
-                let v = self.values.remove(0);
-                self.values.push(v);
-            }
+        if !self.values.is_empty() {
+            self.values.rotate_left(1);
         }
     }
+
+    pub fn reverse(&mut self) {
+        self.values.make_contiguous().reverse();
+    }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StackOfStacks<T> {
     pub initial_stack: Stack<T>,
     pub additional_stacks: Vec<Stack<T>>,
@@ -160,6 +166,11 @@ where
         }
     }
 
+    /// Returns the number of values held by all the stacks.
+    pub fn total_len(&self) -> usize {
+        self.initial_stack.len() + self.additional_stacks.iter().map(Stack::len).sum::<usize>()
+    }
+
     pub fn top(&self) -> &Stack<T> {
         self.additional_stacks.last().unwrap_or(&self.initial_stack)
     }
@@ -380,9 +391,36 @@ mod tests {
 
         assert_eq!(stack.values, vec![2, 3, 4, 1]);
     }
+
+    #[test]
+    fn shift_empty_stack_does_nothing() {
+        let mut stack = Stack::<isize>::new();
+
+        stack.rshift();
+        stack.lshift();
+
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn reverse_after_shifts_works() {
+        let mut stack = Stack::new();
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+
+        stack.lshift();
+        stack.lshift();
+        stack.push(4);
+        stack.reverse();
+
+        assert_eq!(stack.values, vec![4, 2, 1, 3]);
+        assert_eq!(stack.pop(), Some(3));
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::let_unit_value)]
 mod stack_of_stacks_tests {
     use super::*;
 
@@ -457,6 +495,18 @@ mod stack_of_stacks_tests {
         assert_eq!(res, Err(Error::StackUnderflow));
     }
 
+    #[test]
+    fn total_len_works() {
+        let mut s = StackOfStacks::new();
+        s.top_mut().push(5);
+        s.top_mut().push(42);
+        s.top_mut().push(58);
+        s.push_stack(1).unwrap();
+        s.top_mut().push(7);
+
+        assert_eq!(s.total_len(), 4);
+    }
+
     #[test]
     fn pop_stack_works() {
         let mut s = StackOfStacks::new();
@@ -0,0 +1,199 @@
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::{self, BufRead};
+
+/// First difference between two traces written by `Interpreter::dump_state`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// Index of the first differing state, starting at 1.
+    pub step: usize,
+    /// State of the first trace, `None` if it ended before.
+    pub left: Option<Map<String, Value>>,
+    /// State of the second trace, `None` if it ended before.
+    pub right: Option<Map<String, Value>>,
+}
+
+/// Compares two traces of JSON states, one per line, and returns the first difference.
+///
+/// Lines that are not interpreter states, such as the seed or error messages, are ignored.
+/// Numbers are compared by value when `float_tolerance` is given, so that `3` and `3.0`
+/// are the same, and floats may differ by this relative amount.
+pub fn diff_traces<A: BufRead, B: BufRead>(
+    a: A,
+    b: B,
+    float_tolerance: Option<f64>,
+) -> io::Result<Option<Divergence>> {
+    let mut a = states(a);
+    let mut b = states(b);
+    let mut step = 0;
+
+    loop {
+        step += 1;
+        let (left, right) = match (a.next().transpose()?, b.next().transpose()?) {
+            (None, None) => return Ok(None),
+            (left, right) => (left, right),
+        };
+        let same = match (&left, &right) {
+            (Some(l), Some(r)) => {
+                l.len() == r.len()
+                    && l.iter().all(|(k, v)| {
+                        r.get(k)
+                            .is_some_and(|w| values_match(v, w, float_tolerance))
+                    })
+            }
+            _ => false,
+        };
+        if !same {
+            return Ok(Some(Divergence { step, left, right }));
+        }
+    }
+}
+
+/// Iterates over the states of a trace.
+fn states<R: BufRead>(r: R) -> impl Iterator<Item = io::Result<Map<String, Value>>> {
+    r.lines().enumerate().filter_map(|(n, line)| {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        if !line.trim_start().starts_with('{') {
+            return None;
+        }
+        match serde_json::from_str::<Map<String, Value>>(&line) {
+            Ok(state) if state.contains_key("ip") => Some(Ok(state)),
+            Ok(_) => None,
+            Err(e) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", n + 1, e),
+            ))),
+        }
+    })
+}
+
+fn values_match(a: &Value, b: &Value, float_tolerance: Option<f64>) -> bool {
+    match (a, b, float_tolerance) {
+        (Value::Number(x), Value::Number(y), Some(tolerance)) => match (x.as_f64(), y.as_f64()) {
+            (Some(x), Some(y)) => x == y || (x - y).abs() <= tolerance * x.abs().max(y.abs()),
+            _ => x == y,
+        },
+        (Value::Array(x), Value::Array(y), Some(_)) => {
+            x.len() == y.len()
+                && x.iter()
+                    .zip(y)
+                    .all(|(x, y)| values_match(x, y, float_tolerance))
+        }
+        _ => a == b,
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "traces diverge at step {}:", self.step)?;
+
+        let empty = Map::new();
+        let left = self.left.as_ref().unwrap_or(&empty);
+        let right = self.right.as_ref().unwrap_or(&empty);
+        let mut keys: Vec<&String> = left.keys().collect();
+        keys.extend(right.keys().filter(|k| !left.contains_key(*k)));
+
+        let show = |state: &Option<Map<String, Value>>, key: &str| match state {
+            Some(s) => s.get(key).map_or("-".to_string(), Value::to_string),
+            None => "(end of trace)".to_string(),
+        };
+        let rows: Vec<_> = keys
+            .iter()
+            .map(|k| (k.as_str(), show(&self.left, k), show(&self.right, k)))
+            .collect();
+
+        let key_width = rows.iter().map(|(k, _, _)| k.len()).max().unwrap_or(0);
+        let left_width = rows
+            .iter()
+            .map(|(_, l, _)| l.len())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        writeln!(
+            f,
+            "  {:kw$}  {:lw$}  B",
+            "",
+            "A",
+            kw = key_width,
+            lw = left_width
+        )?;
+        for (k, l, r) in rows {
+            let mark = if l == r { ' ' } else { '*' };
+            writeln!(
+                f,
+                "{} {:kw$}  {:lw$}  {}",
+                mark,
+                k,
+                l,
+                r,
+                kw = key_width,
+                lw = left_width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE: &str = r#"{"seed":5}
+{"ip":[0,0],"dir":"right","next_instr":"1","stack":[],"register":null}
+{"ip":[1,0],"dir":"right","next_instr":"2","stack":[1],"register":null}
+{"ip":[2,0],"dir":"right","next_instr":",","stack":[1,2],"register":null}
+{"ip":[3,0],"dir":"right","next_instr":"n","stack":[0.5],"register":null}
+"#;
+
+    #[test]
+    fn identical_traces_do_not_diverge() {
+        let other = TRACE
+            .replace("{\"seed\":5}\n", "")
+            .replace("[1,2]", "[1.0,2.0]");
+
+        assert_eq!(
+            diff_traces(TRACE.as_bytes(), TRACE.as_bytes(), None).unwrap(),
+            None
+        );
+        assert_eq!(
+            diff_traces(TRACE.as_bytes(), other.as_bytes(), Some(0.0)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn first_divergence_is_reported() {
+        let other = TRACE
+            .replace("[1,2]", "[1.0,2.0]")
+            .replace("0.5", "0.50001");
+
+        let d = diff_traces(TRACE.as_bytes(), other.as_bytes(), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(d.step, 3);
+        assert!(d.to_string().contains("* stack"));
+
+        let d = diff_traces(TRACE.as_bytes(), other.as_bytes(), Some(1e-6))
+            .unwrap()
+            .unwrap();
+        assert_eq!(d.step, 4);
+        assert!(diff_traces(TRACE.as_bytes(), other.as_bytes(), Some(1e-4))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn shorter_trace_diverges() {
+        let other: String = TRACE.lines().take(3).map(|l| format!("{}\n", l)).collect();
+
+        let d = diff_traces(TRACE.as_bytes(), other.as_bytes(), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(d.step, 3);
+        assert!(d.right.is_none());
+        assert!(d.to_string().contains("(end of trace)"));
+    }
+}
@@ -1,18 +1,98 @@
-use std::fmt;
-
-#[derive(Debug, Clone)]
+#[cfg(feature = "bignum")]
+use num_bigint::BigInt;
+#[cfg(feature = "rational")]
+use num_rational::BigRational;
+#[cfg(feature = "bignum")]
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, convert::TryFrom, fmt};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Val {
     Byte(u8),
     Int(i64),
     Float(f64),
+    /// Integer too large for an `i64`, produced by arithmetic on integers.
+    #[cfg(feature = "bignum")]
+    Big(BigInt),
+    /// Exact fraction, produced by `,` in exact division mode. Never an integer.
+    #[cfg(feature = "rational")]
+    Ratio(BigRational),
+}
+
+/// What arithmetic on integers does when its result does not fit in an `i64`.
+///
+/// `%` is not affected, as its result is always smaller than its divisor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowPolicy {
+    /// Fail with an integer overflow error.
+    Error,
+    /// Wrap around, as with two's complement arithmetic.
+    Wrap,
+    /// Clamp the result to the smallest or largest `i64`.
+    Saturate,
+    /// Compute the result as a float.
+    Float,
+    /// Promote the result to a big integer.
+    #[cfg(feature = "bignum")]
+    Bignum,
+}
+
+impl Default for OverflowPolicy {
+    /// Promotes to big integers when available, like the reference interpreter,
+    /// and fails otherwise.
+    fn default() -> Self {
+        #[cfg(feature = "bignum")]
+        {
+            OverflowPolicy::Bignum
+        }
+        #[cfg(not(feature = "bignum"))]
+        {
+            OverflowPolicy::Error
+        }
+    }
+}
+
+/// Applies an integer operation, handling overflows according to a policy.
+macro_rules! int_op {
+    ($a:expr, $b:expr, $policy:expr, $checked:ident, $wrapping:ident, $saturating:ident, $op:tt) => {{
+        let (x, y) = match ($a.to_small(), $b.to_small()) {
+            (Some(x), Some(y)) => (x, y),
+            #[cfg(feature = "bignum")]
+            _ => return Val::fit_big($a.to_bigint() $op $b.to_bigint(), $policy),
+            #[cfg(not(feature = "bignum"))]
+            _ => ($a.to_i64(), $b.to_i64()),
+        };
+        match x.$checked(y) {
+            Some(res) => Some(Val::Int(res)),
+            None => match $policy {
+                OverflowPolicy::Error => None,
+                OverflowPolicy::Wrap => Some(Val::Int(x.$wrapping(y))),
+                OverflowPolicy::Saturate => Some(Val::Int(x.$saturating(y))),
+                OverflowPolicy::Float => Some(Val::Float(x as f64 $op y as f64)),
+                #[cfg(feature = "bignum")]
+                OverflowPolicy::Bignum => Some(Val::Big(BigInt::from(x) $op BigInt::from(y))),
+            },
+        }
+    }};
 }
 
 impl Val {
+    /// Returns the integer part of the value, big integers saturate.
     pub fn to_i64(&self) -> i64 {
         match *self {
             Val::Byte(val) => i64::from(val),
             Val::Int(val) => val,
             Val::Float(val) => val.trunc() as i64,
+            #[cfg(feature = "bignum")]
+            Val::Big(ref val) => val.to_i64().unwrap_or(if val.is_negative() {
+                i64::MIN
+            } else {
+                i64::MAX
+            }),
+            #[cfg(feature = "rational")]
+            Val::Ratio(_) => Val::from_big(self.to_bigint()).to_i64(),
         }
     }
 
@@ -21,6 +101,10 @@ impl Val {
             Val::Byte(val) => val,
             Val::Int(val) => val as u8,
             Val::Float(val) => val.trunc() as u8,
+            #[cfg(feature = "bignum")]
+            Val::Big(ref val) => (val % 256u32).to_i64().unwrap_or(0) as u8,
+            #[cfg(feature = "rational")]
+            Val::Ratio(_) => Val::Big(self.to_bigint()).to_u8(),
         }
     }
 
@@ -29,31 +113,237 @@ impl Val {
             Val::Byte(val) => f64::from(val),
             Val::Int(val) => val as f64,
             Val::Float(val) => val,
+            #[cfg(feature = "bignum")]
+            Val::Big(ref val) => val.to_f64().unwrap_or(f64::NAN),
+            #[cfg(feature = "rational")]
+            Val::Ratio(ref val) => val.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    /// Returns the integer part of the value as a big integer.
+    #[cfg(feature = "bignum")]
+    pub fn to_bigint(&self) -> BigInt {
+        match *self {
+            Val::Byte(val) => BigInt::from(val),
+            Val::Int(val) => BigInt::from(val),
+            Val::Float(val) => BigInt::from_f64(val.trunc()).unwrap_or_default(),
+            Val::Big(ref val) => val.clone(),
+            #[cfg(feature = "rational")]
+            Val::Ratio(ref val) => val.to_integer(),
+        }
+    }
+
+    /// Returns the exact value as a fraction, NaN and infinite floats are zero.
+    #[cfg(feature = "rational")]
+    pub fn to_ratio(&self) -> BigRational {
+        match *self {
+            Val::Float(val) => BigRational::from_float(val).unwrap_or_default(),
+            Val::Ratio(ref val) => val.clone(),
+            _ => BigRational::from_integer(self.to_bigint()),
+        }
+    }
+
+    /// Returns the smallest representation of a fraction.
+    #[cfg(feature = "rational")]
+    pub fn from_ratio(val: BigRational) -> Val {
+        if val.is_integer() {
+            Val::from_big(val.to_integer())
+        } else {
+            Val::Ratio(val)
+        }
+    }
+
+    /// Divides two values exactly, returns `None` if the divisor is zero.
+    ///
+    /// The result is a float if one of the values is a float.
+    #[cfg(feature = "rational")]
+    pub fn div_exact(&self, other: &Self) -> Option<Val> {
+        match (self, other) {
+            (Val::Float(_), _) | (_, Val::Float(_)) => {
+                let res = self.to_f64() / other.to_f64();
+                if res.is_infinite() {
+                    None
+                } else {
+                    Some(Val::Float(res))
+                }
+            }
+            _ => {
+                let x = other.to_ratio();
+                if x.is_zero() {
+                    return None;
+                }
+                Some(Val::from_ratio(self.to_ratio() / x))
+            }
+        }
+    }
+
+    #[cfg(feature = "rational")]
+    fn is_ratio(&self) -> bool {
+        matches!(self, Val::Ratio(_))
+    }
+
+    /// Returns the smallest representation of a big integer.
+    #[cfg(feature = "bignum")]
+    pub fn from_big(val: BigInt) -> Val {
+        match val.to_i64() {
+            Some(v) => Val::Int(v),
+            None => Val::Big(val),
+        }
+    }
+
+    /// Converts the result of an operation on big integers according to the overflow policy.
+    #[cfg(feature = "bignum")]
+    fn fit_big(val: BigInt, policy: OverflowPolicy) -> Option<Val> {
+        if let Some(v) = val.to_i64() {
+            return Some(Val::Int(v));
+        }
+        match policy {
+            OverflowPolicy::Error => None,
+            OverflowPolicy::Wrap => Some(Val::Int(
+                (val & BigInt::from(u64::MAX)).to_u64().unwrap_or(0) as i64,
+            )),
+            OverflowPolicy::Saturate => Some(Val::Int(if val.is_negative() {
+                i64::MIN
+            } else {
+                i64::MAX
+            })),
+            OverflowPolicy::Float => Some(Val::Float(val.to_f64().unwrap_or(f64::NAN))),
+            OverflowPolicy::Bignum => Some(Val::Big(val)),
+        }
+    }
+
+    /// Returns the value of an integer that fits in an `i64`.
+    fn to_small(&self) -> Option<i64> {
+        match *self {
+            Val::Byte(val) => Some(i64::from(val)),
+            Val::Int(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Compares the integer parts of two values,
+    /// or their exact values if one of them is a fraction.
+    pub fn cmp_int(&self, other: &Self) -> Ordering {
+        #[cfg(feature = "rational")]
+        {
+            if self.is_ratio() || other.is_ratio() {
+                return self.to_ratio().cmp(&other.to_ratio());
+            }
         }
+        #[cfg(feature = "bignum")]
+        {
+            if matches!(self, Val::Big(_)) || matches!(other, Val::Big(_)) {
+                return self.to_bigint().cmp(&other.to_bigint());
+            }
+        }
+        self.to_i64().cmp(&other.to_i64())
+    }
+
+    /// Compares the actual values, floats included, `None` if one of them is NaN.
+    pub fn partial_cmp_real(&self, other: &Self) -> Option<Ordering> {
+        if matches!(self, Val::Float(_)) || matches!(other, Val::Float(_)) {
+            return self.to_f64().partial_cmp(&other.to_f64());
+        }
+        Some(self.cmp_int(other))
     }
 
+    /// Adds two values with the default overflow policy: results too large for an
+    /// `i64` become big integers with the `bignum` feature, and `None` without it.
     pub fn checked_add(&self, other: &Self) -> Option<Val> {
+        self.add_with(other, OverflowPolicy::default())
+    }
+
+    /// Subtracts two values with the default overflow policy, see `checked_add`.
+    pub fn checked_sub(&self, other: &Self) -> Option<Val> {
+        self.sub_with(other, OverflowPolicy::default())
+    }
+
+    /// Multiplies two values with the default overflow policy, see `checked_add`.
+    pub fn checked_mul(&self, other: &Self) -> Option<Val> {
+        self.mul_with(other, OverflowPolicy::default())
+    }
+
+    /// Adds two values, returns `None` if the result overflows and the policy is `Error`.
+    pub fn add_with(&self, other: &Self, policy: OverflowPolicy) -> Option<Val> {
         match (self, other) {
             (Val::Float(f), v) => Some(Val::Float(f + v.to_f64())),
             (v, Val::Float(f)) => Some(Val::Float(v.to_f64() + f)),
-            _ => self.to_i64().checked_add(other.to_i64()).map(Val::Int),
+            #[cfg(feature = "rational")]
+            (Val::Ratio(_), _) | (_, Val::Ratio(_)) => {
+                Some(Val::from_ratio(self.to_ratio() + other.to_ratio()))
+            }
+            _ => int_op!(self, other, policy, checked_add, wrapping_add, saturating_add, +),
         }
     }
 
-    pub fn checked_sub(&self, other: &Self) -> Option<Val> {
+    /// Subtracts two values, returns `None` if the result overflows and the policy is `Error`.
+    pub fn sub_with(&self, other: &Self, policy: OverflowPolicy) -> Option<Val> {
         match (self, other) {
             (Val::Float(f), v) => Some(Val::Float(f - v.to_f64())),
             (v, Val::Float(f)) => Some(Val::Float(v.to_f64() - f)),
-            _ => self.to_i64().checked_sub(other.to_i64()).map(Val::Int),
+            #[cfg(feature = "rational")]
+            (Val::Ratio(_), _) | (_, Val::Ratio(_)) => {
+                Some(Val::from_ratio(self.to_ratio() - other.to_ratio()))
+            }
+            _ => int_op!(self, other, policy, checked_sub, wrapping_sub, saturating_sub, -),
         }
     }
 
-    pub fn checked_mul(&self, other: &Self) -> Option<Val> {
+    /// Multiplies two values, returns `None` if the result overflows and the policy is `Error`.
+    pub fn mul_with(&self, other: &Self, policy: OverflowPolicy) -> Option<Val> {
         match (self, other) {
             (Val::Float(f), v) => Some(Val::Float(f * v.to_f64())),
             (v, Val::Float(f)) => Some(Val::Float(v.to_f64() * f)),
-            _ => self.to_i64().checked_mul(other.to_i64()).map(Val::Int),
+            #[cfg(feature = "rational")]
+            (Val::Ratio(_), _) | (_, Val::Ratio(_)) => {
+                Some(Val::from_ratio(self.to_ratio() * other.to_ratio()))
+            }
+            _ => int_op!(self, other, policy, checked_mul, wrapping_mul, saturating_mul, *),
+        }
+    }
+
+    /// Returns the remainder of the division of the integer parts, with the sign
+    /// of the divisor as in Python, or `None` if the divisor is zero.
+    ///
+    /// Fractions are not truncated, the result is then exact.
+    pub fn checked_rem(&self, other: &Self) -> Option<Val> {
+        #[cfg(feature = "rational")]
+        {
+            if self.is_ratio() || other.is_ratio() {
+                let x = other.to_ratio();
+                if x.is_zero() {
+                    return None;
+                }
+                let y = self.to_ratio();
+                let quotient = (&y / &x).floor();
+                return Some(Val::from_ratio(y - x * quotient));
+            }
+        }
+        #[cfg(feature = "bignum")]
+        {
+            if matches!(self, Val::Big(_)) || matches!(other, Val::Big(_)) {
+                let x = other.to_bigint();
+                if x.is_zero() {
+                    return None;
+                }
+                let mut rem = self.to_bigint() % &x;
+                if !rem.is_zero() && rem.is_negative() != x.is_negative() {
+                    rem += x;
+                }
+                return Some(Val::from_big(rem));
+            }
         }
+
+        let x = other.to_i64();
+        if x == 0 {
+            return None;
+        }
+        let rem = self.to_i64().wrapping_rem(x);
+        Some(Val::Int(if rem != 0 && (rem < 0) != (x < 0) {
+            rem + x
+        } else {
+            rem
+        }))
     }
 }
 
@@ -63,6 +353,16 @@ impl From<u8> for Val {
     }
 }
 
+/// Characters are their Unicode code point.
+impl From<char> for Val {
+    fn from(c: char) -> Self {
+        match u8::try_from(c) {
+            Ok(b) => Val::Byte(b),
+            Err(_) => Val::Int(i64::from(u32::from(c))),
+        }
+    }
+}
+
 impl From<i64> for Val {
     fn from(v: i64) -> Self {
         Val::Int(v)
@@ -98,22 +398,27 @@ impl PartialEq for Val {
         match (self, other) {
             (Val::Float(a), Val::Float(b)) => a == b,
             (Val::Float(_), _) | (_, Val::Float(_)) => false,
-            _ => self.to_i64() == other.to_i64(),
+            _ => self.cmp_int(other) == Ordering::Equal,
         }
     }
 }
 
 impl fmt::Display for Val {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
+        match self {
             Val::Byte(val) => write!(f, "{}", val),
             Val::Int(val) => write!(f, "{}", val),
             Val::Float(val) => write!(f, "{}", val),
+            #[cfg(feature = "bignum")]
+            Val::Big(val) => write!(f, "{}", val),
+            #[cfg(feature = "rational")]
+            Val::Ratio(val) => write!(f, "{}", val),
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use super::*;
 
@@ -198,4 +503,163 @@ mod tests {
         assert_ne!(Val::Byte(1), Val::Float(1.0));
         assert_ne!(Val::Int(1), Val::Float(1.0));
     }
+
+    #[test]
+    fn partial_cmp_real_works() {
+        assert_eq!(
+            Val::Float(0.5).partial_cmp_real(&Val::Byte(0)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            Val::Int(-1).partial_cmp_real(&Val::Float(-0.5)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            Val::Int(3).partial_cmp_real(&Val::Byte(3)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(Val::Float(f64::NAN).partial_cmp_real(&Val::Int(0)), None);
+    }
+
+    #[test]
+    fn checked_rem_works() {
+        assert_eq!(Val::Int(-1).checked_rem(&Val::Int(13)), Some(Val::Int(12)));
+        assert_eq!(Val::Int(1).checked_rem(&Val::Int(-13)), Some(Val::Int(-12)));
+        assert_eq!(Val::Int(26).checked_rem(&Val::Byte(13)), Some(Val::Int(0)));
+        assert_eq!(
+            Val::Int(i64::MIN).checked_rem(&Val::Int(-1)),
+            Some(Val::Int(0))
+        );
+        assert_eq!(Val::Int(5).checked_rem(&Val::Float(0.5)), None);
+    }
+
+    #[cfg(not(feature = "bignum"))]
+    #[test]
+    fn overflow_fails() {
+        assert_eq!(Val::Int(i64::MAX).checked_add(&Val::Byte(1)), None);
+        assert_eq!(Val::Int(i64::MIN).checked_sub(&Val::Byte(1)), None);
+        assert_eq!(Val::Int(i64::MAX).checked_mul(&Val::Byte(2)), None);
+    }
+
+    #[test]
+    fn overflow_policies_work() {
+        let max = Val::Int(i64::MAX);
+        let min = Val::Int(i64::MIN);
+        let two = Val::Byte(2);
+
+        assert_eq!(
+            max.add_with(&two, OverflowPolicy::Wrap),
+            Some(Val::Int(i64::MIN + 1))
+        );
+        assert_eq!(
+            min.sub_with(&two, OverflowPolicy::Wrap),
+            Some(Val::Int(i64::MAX - 1))
+        );
+        assert_eq!(max.mul_with(&two, OverflowPolicy::Wrap), Some(Val::Int(-2)));
+
+        assert_eq!(
+            max.add_with(&two, OverflowPolicy::Saturate),
+            Some(max.clone())
+        );
+        assert_eq!(
+            min.sub_with(&two, OverflowPolicy::Saturate),
+            Some(min.clone())
+        );
+        assert_eq!(
+            min.mul_with(&two, OverflowPolicy::Saturate),
+            Some(min.clone())
+        );
+
+        assert_eq!(
+            max.mul_with(&two, OverflowPolicy::Float),
+            Some(Val::Float(i64::MAX as f64 * 2.0))
+        );
+        assert_eq!(
+            max.add_with(&Val::Byte(0), OverflowPolicy::Error),
+            Some(max)
+        );
+    }
+
+    #[cfg(feature = "bignum")]
+    #[test]
+    fn overflow_promotes_to_bignum() {
+        let big = Val::Int(i64::MAX)
+            .add_with(&Val::Byte(1), OverflowPolicy::Bignum)
+            .unwrap();
+        assert_eq!(big.to_string(), "9223372036854775808");
+        assert_eq!(big.to_i64(), i64::MAX);
+        assert_eq!(big.to_u8(), 0);
+        assert!(big.cmp_int(&Val::Int(i64::MAX)) == Ordering::Greater);
+
+        let back = big.checked_sub(&Val::Byte(1)).unwrap();
+        assert!(matches!(back, Val::Int(i64::MAX)));
+        assert_eq!(big.add_with(&Val::Byte(1), OverflowPolicy::Error), None);
+        assert_eq!(
+            big.add_with(&Val::Byte(1), OverflowPolicy::Wrap),
+            Some(Val::Int(i64::MIN + 1))
+        );
+        assert_eq!(
+            big.add_with(&Val::Byte(1), OverflowPolicy::Saturate),
+            Some(Val::Int(i64::MAX))
+        );
+
+        let square = big.mul_with(&big, OverflowPolicy::Bignum).unwrap();
+        assert_eq!(square.to_string(), "85070591730234615865843651857942052864");
+        assert_eq!(square.checked_rem(&Val::Int(-7)), Some(Val::Int(-6)));
+    }
+
+    #[cfg(feature = "bignum")]
+    #[test]
+    fn checked_overflow_promotes_to_bignum() {
+        let max = Val::Int(i64::MAX);
+        let min = Val::Int(i64::MIN);
+
+        let sum = max.checked_add(&Val::Byte(1)).unwrap();
+        assert!(matches!(sum, Val::Big(_)));
+        assert_eq!(sum.to_string(), "9223372036854775808");
+
+        let difference = min.checked_sub(&Val::Byte(1)).unwrap();
+        assert!(matches!(difference, Val::Big(_)));
+        assert_eq!(difference.to_string(), "-9223372036854775809");
+
+        let product = max.checked_mul(&Val::Byte(2)).unwrap();
+        assert!(matches!(product, Val::Big(_)));
+        assert_eq!(product.to_string(), "18446744073709551614");
+
+        let back = product.checked_sub(&max).unwrap().checked_sub(&max);
+        assert!(matches!(back, Some(Val::Int(0))));
+    }
+
+    #[cfg(feature = "rational")]
+    #[test]
+    fn exact_division_works() {
+        let third = Val::Byte(1).div_exact(&Val::Byte(3)).unwrap();
+        assert_eq!(third.to_string(), "1/3");
+        assert_eq!(third.to_i64(), 0);
+
+        let one = third.checked_mul(&Val::Byte(3)).unwrap();
+        assert!(matches!(one, Val::Int(1)));
+
+        let sum = third.checked_add(&third).unwrap();
+        assert_eq!(sum.to_string(), "2/3");
+        assert_eq!(sum.cmp_int(&Val::Float(2.0 / 3.0)), Ordering::Greater);
+        assert_eq!(sum.cmp_int(&Val::Byte(0)), Ordering::Greater);
+
+        assert_eq!(Val::Byte(1).div_exact(&Val::Byte(0)), None);
+        assert_eq!(
+            Val::Byte(1).div_exact(&Val::Float(4.0)),
+            Some(Val::Float(0.25))
+        );
+    }
+
+    #[cfg(feature = "rational")]
+    #[test]
+    fn exact_rem_works() {
+        let half = Val::Byte(1).div_exact(&Val::Byte(2)).unwrap();
+        let rem = Val::Byte(7).div_exact(&Val::Byte(3)).unwrap();
+
+        assert_eq!(rem.checked_rem(&half).unwrap().to_string(), "1/3");
+        assert_eq!(Val::Int(-1).checked_rem(&half).unwrap().to_string(), "0");
+        assert_eq!(half.checked_rem(&Val::Int(-1)).unwrap().to_string(), "-1/2");
+    }
 }
@@ -0,0 +1,140 @@
+use fish::{CodeBox, Direction, Interpreter, StepOutcome};
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::io::{self, prelude::*};
+use std::rc::Rc;
+use std::{thread, time::Duration};
+
+/// Delay between frames when no tick is given on the command line.
+pub const DEFAULT_TICK: Duration = Duration::from_millis(100);
+
+/// Number of output lines displayed below the codebox.
+const OUTPUT_LINES: usize = 10;
+
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+const HIGHLIGHT: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+
+/// Program output, captured so that it can be displayed below the codebox.
+#[derive(Clone, Default)]
+pub struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs the program from the current position, redrawing the codebox after every instruction.
+pub fn run<R: Read>(
+    fish: &mut Interpreter<R, Output>,
+    code: &CodeBox,
+    output: &Output,
+    tick: Duration,
+    always_tick: bool,
+) -> fish::Result<()> {
+    draw(fish, code, output);
+
+    loop {
+        let outcome = fish.step(code);
+        draw(fish, code, output);
+
+        match outcome? {
+            StepOutcome::Stopped { .. } => return Ok(()),
+            outcome => {
+                if outcome.instruction() != b' ' || always_tick {
+                    thread::sleep(tick);
+                }
+            }
+        }
+    }
+}
+
+fn arrow(dir: &Direction) -> char {
+    match dir {
+        Direction::Right => '→',
+        Direction::Left => '←',
+        Direction::Up => '↑',
+        Direction::Down => '↓',
+    }
+}
+
+fn draw<R: Read>(fish: &Interpreter<R, Output>, code: &CodeBox, output: &Output) {
+    let mut frame = String::from(CLEAR_SCREEN);
+
+    let _ = writeln!(
+        frame,
+        "step {}  ip ({}, {}) {}",
+        fish.steps,
+        fish.ip.chr,
+        fish.ip.line,
+        arrow(&fish.dir)
+    );
+    frame.push('\n');
+
+    // the lines and columns between the code and the cells written far from it are elided
+    let mut view = fish.view(code);
+    view.include(fish.ip.chr, fish.ip.line);
+    let columns = view.columns();
+    for y in view.lines() {
+        let y = match y {
+            Some(y) => y,
+            None => {
+                frame.push_str("…\n");
+                continue;
+            }
+        };
+        for &x in &columns {
+            let x = match x {
+                Some(x) => x,
+                None => {
+                    frame.push('…');
+                    continue;
+                }
+            };
+            let c = match fish.cell_at(code, x, y) {
+                Some(c) if c.is_ascii_graphic() => c as char,
+                Some(b' ') | None => ' ',
+                Some(_) => '?',
+            };
+            if x == fish.ip.chr && y == fish.ip.line {
+                let _ = write!(frame, "{}{}{}", HIGHLIGHT, c, RESET);
+            } else {
+                frame.push(c);
+            }
+        }
+        frame.push('\n');
+    }
+    frame.push('\n');
+
+    let top = fish.stack.top();
+    let values: Vec<String> = top.values.iter().map(|v| v.to_string()).collect();
+    let _ = writeln!(frame, "stack:    [{}]", values.join(", "));
+    let _ = writeln!(
+        frame,
+        "register: {}",
+        top.register
+            .as_ref()
+            .map_or("empty".to_string(), |v| v.to_string())
+    );
+    frame.push('\n');
+
+    let out = output.0.borrow();
+    let text = String::from_utf8_lossy(&out);
+    let lines: Vec<&str> = text.lines().collect();
+    let first = lines.len().saturating_sub(OUTPUT_LINES);
+    frame.push_str("output:\n");
+    for line in &lines[first..] {
+        frame.push_str(line);
+        frame.push('\n');
+    }
+
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(frame.as_bytes());
+    let _ = stdout.flush();
+}
@@ -0,0 +1,230 @@
+//! Differential tests against the reference interpreter `fish.py`.
+//!
+//! Random programs are run by `Interpreter` in `fish.py` compatibility mode and by
+//! `fish.py` itself, through `fish_py_driver.py`. Their output, outcome and traces
+//! must be the same. A mismatch is reported with a minimized program reproducing it.
+//!
+//! The test takes a while and needs `python3`, so it only runs when asked for:
+//! `cargo test --test differential_test -- --ignored`. The number of programs and
+//! the seed can be changed with the `FISHR_DIFF_CASES` and `FISHR_DIFF_SEED`
+//! environment variables. The test is skipped when `python3` cannot be found.
+
+use fish::*;
+use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const MAX_STEPS: u64 = 2000;
+
+/// Instructions of generated programs, repeated to make them more likely.
+/// `x` is left out as the random directions of both interpreters differ.
+const CELLS: &[u8] =
+    b"   0123456789abcdef0123456789+-*,%=)(+-*:~$@}{rl[]&onigp;?!><^v><^v/\\|_#.\"'";
+const INPUT: &[u8] = b"ab1 \n";
+
+#[derive(Clone, Debug)]
+struct Case {
+    code: Vec<Vec<u8>>,
+    input: String,
+}
+
+impl Case {
+    fn random(rng: &mut ChaCha12Rng) -> Case {
+        let width = rng.gen_range(1..=10);
+        let height = rng.gen_range(1..=4);
+        let code = (0..height)
+            .map(|_| {
+                (0..width)
+                    .map(|_| *CELLS.choose(rng).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mut input: String = (0..rng.gen_range(0..4))
+            .map(|_| *INPUT.choose(rng).unwrap() as char)
+            .collect();
+        if rng.gen_bool(0.2) {
+            input.push('é');
+        }
+        Case { code, input }
+    }
+
+    fn code(&self) -> String {
+        self.code
+            .iter()
+            .map(|line| String::from_utf8_lossy(line).trim_end().to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// How a run ended.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    output: String,
+    finished: bool,
+    failed: bool,
+}
+
+/// Runs a case with fishr, returns `None` if it does not end within the step limit
+/// or needs integers that do not fit in 64 bits.
+fn run_fishr(case: &Case) -> Option<Outcome> {
+    let mut cb = CodeBox::load_from_string(&case.code());
+    cb.strip_hashbang();
+    cb.decode_utf8();
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(case.input.as_bytes(), &mut output);
+    interpreter.compat = Compat::FishPy;
+    interpreter.limits.max_steps = Some(MAX_STEPS);
+
+    let result = interpreter.run(&cb);
+    match result {
+        Err(RuntimeError::StepLimitExceeded) => return None,
+        Err(RuntimeError::IntegerOverflow) if !cfg!(feature = "bignum") => return None,
+        _ => {}
+    }
+    Some(Outcome {
+        output: String::from_utf8_lossy(&output).into_owned(),
+        finished: true,
+        failed: result.is_err(),
+    })
+}
+
+fn trace_fishr(case: &Case) -> String {
+    let out = run_with_input(
+        Command::new(env!("CARGO_BIN_EXE_fishr"))
+            .args(["--compat", "fishpy", "--debug", "--max-steps"])
+            .arg(MAX_STEPS.to_string())
+            .arg(format!("--code={}", case.code())),
+        &case.input,
+    );
+    String::from_utf8_lossy(&out.stderr).into_owned()
+}
+
+/// Runs a case with fish.py, returns the outcome and the trace.
+fn run_fish_py(case: &Case) -> (Outcome, String) {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let out = run_with_input(
+        Command::new("python3")
+            .arg(format!("{}/tests/fish_py_driver.py", dir))
+            .arg(format!("{}/fish.py", dir))
+            .arg(case.code())
+            // a skipped cell is a move of its own
+            .arg((2 * MAX_STEPS + 1).to_string())
+            .env("PYTHONIOENCODING", "utf-8"),
+        &case.input,
+    );
+    let result: serde_json::Value =
+        serde_json::from_slice(&out.stdout).expect("invalid output of fish_py_driver.py");
+    let outcome = Outcome {
+        output: result["output"].as_str().unwrap_or_default().to_string(),
+        finished: result["finished"].as_bool().unwrap_or_default(),
+        failed: result["failed"].as_bool().unwrap_or_default(),
+    };
+    (outcome, String::from_utf8_lossy(&out.stderr).into_owned())
+}
+
+fn run_with_input(command: &mut Command, input: &str) -> std::process::Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("cannot run command");
+    // the program may end without reading its input
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+/// Returns a description of the difference between both interpreters, if any.
+fn mismatch(case: &Case) -> Option<String> {
+    let fishr = run_fishr(case)?;
+    let (fish_py, py_trace) = run_fish_py(case);
+    if fishr != fish_py {
+        return Some(format!("fishr: {:?}\nfish.py: {:?}", fishr, fish_py));
+    }
+
+    let trace = trace_fishr(case);
+    if has_big_values(&trace) {
+        return None;
+    }
+    diff_traces(py_trace.as_bytes(), trace.as_bytes(), Some(0.0))
+        .expect("invalid trace")
+        .map(|d| format!("A is fish.py, B is fishr\n{}", d))
+}
+
+/// Returns whether a fishr trace contains big integers, which are traced as strings.
+fn has_big_values(trace: &str) -> bool {
+    trace
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .any(|state| match &state["stack"] {
+            serde_json::Value::Array(values) => values.iter().any(|v| v.is_string()),
+            _ => false,
+        })
+}
+
+/// Simplifies a failing case as long as it keeps failing.
+fn minimize(mut case: Case) -> Case {
+    loop {
+        let mut candidates = vec![];
+        for y in 0..case.code.len() {
+            let mut c = case.clone();
+            c.code.remove(y);
+            candidates.push(c);
+        }
+        for y in 0..case.code.len() {
+            for x in 0..case.code[y].len() {
+                if case.code[y][x] != b' ' {
+                    let mut c = case.clone();
+                    c.code[y][x] = b' ';
+                    candidates.push(c);
+                }
+            }
+        }
+        if !case.input.is_empty() {
+            let mut c = case.clone();
+            c.input.pop();
+            candidates.push(c);
+        }
+
+        match candidates
+            .into_iter()
+            .find(|c| !c.code.is_empty() && mismatch(c).is_some())
+        {
+            Some(c) => case = c,
+            None => return case,
+        }
+    }
+}
+
+#[test]
+#[ignore = "slow, runs fish.py with python3"]
+fn random_programs_behave_like_fish_py() {
+    if Command::new("python3").arg("--version").output().is_err() {
+        eprintln!("python3 not found, skipping differential tests");
+        return;
+    }
+
+    let env = |name, default| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    let cases = env("FISHR_DIFF_CASES", 100);
+    let mut rng = ChaCha12Rng::seed_from_u64(env("FISHR_DIFF_SEED", 0));
+
+    for _ in 0..cases {
+        let case = Case::random(&mut rng);
+        if mismatch(&case).is_some() {
+            let case = minimize(case);
+            panic!(
+                "fishr and fish.py disagree on this program\n{}\nwith input {:?}\n{}",
+                case.code(),
+                case.input,
+                mismatch(&case).unwrap_or_default()
+            );
+        }
+    }
+}
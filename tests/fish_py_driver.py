@@ -0,0 +1,65 @@
+"""
+Runs a program with fish.py for a bounded number of moves, for the differential tests.
+
+usage: fish_py_driver.py FISH_PY CODE MAX_MOVES < INPUT
+
+Prints a JSON object with the output of the program, whether it ended and whether
+it failed. The state before each executed instruction is written to stderr in the
+format of `fishr --debug`, with the register of the current stack.
+"""
+import importlib.util
+import io
+import json
+import sys
+
+spec = importlib.util.spec_from_file_location("fish", sys.argv[1])
+fish = importlib.util.module_from_spec(spec)
+spec.loader.exec_module(fish)
+
+code, max_moves = sys.argv[2], int(sys.argv[3])
+stdout = sys.stdout
+output = io.BytesIO()
+sys.stdout = io.TextIOWrapper(output, encoding="utf-8", write_through=True)
+
+DIRECTIONS = {(1, 0): "right", (-1, 0): "left", (0, 1): "down", (0, -1): "up"}
+
+interpreter = fish.Interpreter(code)
+handle = interpreter._handle_instruction
+
+
+def traced(instruction):
+    if instruction != " ":
+        # fishr only has byte instructions, others are shown as a null byte
+        shown = instruction
+        if instruction is None or ord(instruction) > 255:
+            shown = "\0"
+        state = {
+            "ip": interpreter._position,
+            "dir": DIRECTIONS[interpreter._direction],
+            "next_instr": shown,
+            "stack": interpreter._stack,
+            "register": interpreter._register_stack[-1],
+        }
+        sys.stderr.write(json.dumps(state, separators=(",", ":")) + "\n")
+    handle(instruction)
+
+
+interpreter._handle_instruction = traced
+
+finished = failed = False
+for _ in range(max_moves):
+    try:
+        interpreter.move()
+    except fish.StopExecution as stop:
+        finished, failed = True, stop.message is not None
+        break
+    except Exception:
+        # errors outside of instructions, such as moving left on an empty line
+        finished, failed = True, True
+        break
+
+stdout.write(json.dumps({
+    "output": output.getvalue().decode("utf-8", errors="replace"),
+    "finished": finished,
+    "failed": failed,
+}))
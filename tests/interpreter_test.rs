@@ -1034,6 +1034,7 @@ fn write_memory_with_current_value_is_nop() {
 
     assert!(result.is_ok());
     assert!(interpreter.memory.is_empty());
+    assert!(interpreter.written_cells().is_empty());
 }
 
 #[test]
@@ -1047,3 +1048,750 @@ fn write_memory_with_negative_coordinates_works() {
     assert_eq!(interpreter.stack.top().values, vec![Val::Byte(5)]);
     assert_eq!(interpreter.memory[&MemPos { x: -9, y: -9 }], Val::Byte(5));
 }
+
+#[test]
+fn write_memory_in_code_changes_instruction() {
+    let cb = CodeBox::load_from_string("1'n'70p ;");
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(empty(), &mut output);
+    interpreter.history = Some(History::new(10));
+
+    assert_eq!(interpreter.run(&cb), Ok(()));
+    assert_eq!(interpreter.cell_at(&cb, 7, 0), Some(b'n'));
+    assert!(interpreter.memory.is_empty());
+    assert_eq!(
+        interpreter.written_cells(),
+        vec![(MemPos { x: 7, y: 0 }, Val::Byte(b'n'))]
+    );
+
+    // undoing the write restores the instruction of the code
+    while interpreter.step_back() {}
+    assert_eq!(interpreter.cell_at(&cb, 7, 0), Some(b' '));
+    assert!(interpreter.written_cells().is_empty());
+    drop(interpreter);
+    assert_eq!(output, b"1");
+}
+
+#[test]
+fn report_captures_failure_state() {
+    let cb = CodeBox::load_from_string("12v\n  +\n  +");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    let error = interpreter.run(&cb).unwrap_err();
+    let report = interpreter.report(error, &cb);
+
+    assert_eq!(report.error, RuntimeError::StackUnderflow);
+    assert_eq!(report.ip, InstructionPtr { chr: 2, line: 2 });
+    assert_eq!(report.dir, Direction::Down);
+    assert_eq!(report.instruction, Some(b'+'));
+    assert_eq!(report.steps, 4);
+    assert_eq!(report.stack, vec![Val::Int(3)]);
+    assert_eq!(report.excerpt.len(), 3);
+}
+
+#[test]
+fn report_keeps_operands_of_failing_instruction() {
+    let cb = CodeBox::load_from_string("710,");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    let error = interpreter.run(&cb).unwrap_err();
+    let report = interpreter.report(error, &cb);
+
+    assert_eq!(report.error, RuntimeError::DivideByZero);
+    assert_eq!(report.stack, vec![Val::Byte(7), Val::Byte(1), Val::Byte(0)]);
+}
+
+#[test]
+fn report_display_points_at_failing_cell() {
+    let cb = CodeBox::load_from_string("12v\n  +\n  +");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    let error = interpreter.run(&cb).unwrap_err();
+    let text = interpreter.report(error, &cb).to_string();
+
+    assert!(text.contains("error: stack underflow"));
+    assert!(text.contains("2 |   +\n  |   ^"));
+}
+
+#[test]
+fn report_display_truncates_stack() {
+    let cb = CodeBox::load_from_string("0123456789abcdef01234Z");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    let error = interpreter.run(&cb).unwrap_err();
+    let text = interpreter.report(error, &cb).to_string();
+
+    assert!(
+        text.contains("stack: [… (5 more), 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1, 2, 3, 4]")
+    );
+}
+
+#[test]
+fn step_executes_one_instruction() {
+    let cb = CodeBox::load_from_string("12;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    let result = interpreter.step(&cb);
+
+    assert_eq!(
+        result,
+        Ok(StepOutcome::Continued {
+            instruction: b'1',
+            ip: InstructionPtr { chr: 0, line: 0 }
+        })
+    );
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 1, line: 0 });
+    assert_eq!(interpreter.stack.top().values, vec![Val::Byte(1)]);
+}
+
+#[test]
+fn step_reports_skipped_cell() {
+    let cb = CodeBox::load_from_string("!1;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    let result = interpreter.step(&cb);
+
+    assert_eq!(
+        result,
+        Ok(StepOutcome::Skipped {
+            instruction: b'!',
+            ip: InstructionPtr { chr: 0, line: 0 }
+        })
+    );
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 2, line: 0 });
+}
+
+#[test]
+fn execute_moves_over_skipped_cell() {
+    let cb = CodeBox::load_from_string("!1;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    let result = interpreter.execute(b'!', &cb);
+
+    assert!(matches!(result, Ok(RuntimeStatus::Continue)));
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 1, line: 0 });
+}
+
+#[test]
+fn step_reports_stop() {
+    let cb = CodeBox::load_from_string(";");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    let result = interpreter.step(&cb);
+
+    assert_eq!(
+        result,
+        Ok(StepOutcome::Stopped {
+            instruction: b';',
+            ip: InstructionPtr { chr: 0, line: 0 }
+        })
+    );
+}
+
+#[test]
+fn run_for_suspends_and_resumes() {
+    let cb = CodeBox::load_from_string("1234;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    assert_eq!(interpreter.run_for(&cb, 2), Ok(RunState::Suspended));
+    assert_eq!(
+        interpreter.stack.top().values,
+        vec![Val::Byte(1), Val::Byte(2)]
+    );
+    assert_eq!(interpreter.steps, 2);
+
+    assert_eq!(interpreter.run_for(&cb, 10), Ok(RunState::Finished));
+    assert_eq!(interpreter.stack.top().len(), 4);
+    assert_eq!(interpreter.steps, 5);
+}
+
+#[test]
+fn resume_runs_the_given_code() {
+    let first = CodeBox::load_from_string("'n'20p12;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    assert_eq!(interpreter.run_for(&first, 6), Ok(RunState::Suspended));
+
+    // the cell written in the first code applies to the second one
+    let second = CodeBox::load_from_string("1234 ;");
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 6, line: 0 });
+    interpreter.ip = InstructionPtr { chr: 0, line: 0 };
+    assert_eq!(interpreter.run_for(&second, 10), Ok(RunState::Finished));
+
+    assert_eq!(
+        interpreter.stack.top().values,
+        vec![Val::Byte(1), Val::Byte(4)]
+    );
+    assert_eq!(interpreter.cell_at(&second, 2, 0), Some(b'n'));
+    assert_eq!(interpreter.cell_at(&first, 2, 0), Some(b'n'));
+}
+
+#[test]
+fn step_limit_stops_infinite_loop() {
+    let cb = CodeBox::load_from_string(" ");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.limits.max_steps = Some(100);
+
+    let result = interpreter.run(&cb);
+
+    assert_eq!(result, Err(RuntimeError::StepLimitExceeded));
+    assert_eq!(interpreter.steps, 100);
+}
+
+#[test]
+fn time_limit_stops_infinite_loop() {
+    let cb = CodeBox::load_from_string(" ");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.limits.max_duration = Some(std::time::Duration::from_millis(10));
+
+    let result = interpreter.run(&cb);
+
+    assert_eq!(result, Err(RuntimeError::TimeLimitExceeded));
+}
+
+#[test]
+fn stack_limit_stops_growing_stack() {
+    let cb = CodeBox::load_from_string("l");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.limits.max_stack_size = Some(50);
+
+    let result = interpreter.run(&cb);
+
+    assert_eq!(result, Err(RuntimeError::StackLimitExceeded));
+    assert_eq!(interpreter.stack.top().len(), 51);
+}
+
+#[test]
+fn memory_limit_stops_writing_cells() {
+    let cb = CodeBox::load_from_string(">::2p1+");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.push_i64(1);
+    interpreter.limits.max_memory_cells = Some(10);
+
+    let result = interpreter.run(&cb);
+
+    assert_eq!(result, Err(RuntimeError::MemoryLimitExceeded));
+    assert_eq!(interpreter.memory.len(), 10);
+}
+
+#[test]
+fn seeded_random_direction_is_reproducible() {
+    let cb = CodeBox::load_from_string("v /2v\n 1x3v\n> x<>n;\n 6x4^\n  \\5^");
+    let run = |seed| {
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::new(empty(), &mut output);
+        interpreter.set_seed(seed);
+        for _ in 0..20 {
+            interpreter.run(&cb).unwrap();
+        }
+        output
+    };
+
+    assert_eq!(run(42), run(42));
+    assert_ne!(run(42), run(43));
+}
+
+#[test]
+fn injected_rng_is_used() {
+    let cb = CodeBox::load_from_string("x;");
+    let rng = rand::rngs::mock::StepRng::new(0, 0);
+    let mut interpreter = Interpreter::with_rng(empty(), sink(), rng);
+
+    let result = interpreter.run(&cb);
+
+    assert!(result.is_ok());
+    assert_eq!(interpreter.dir, Direction::Left);
+}
+
+#[test]
+fn always_tick_ticks_on_whitespace_and_skipped_cells() {
+    let cb = CodeBox::load_from_string("   !1;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.tick = Some(std::time::Duration::from_millis(10));
+    interpreter.always_tick = true;
+
+    let start = std::time::Instant::now();
+    let result = interpreter.run(&cb);
+
+    assert!(result.is_ok());
+    assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+}
+
+#[test]
+fn profile_counts_cells_and_instructions() {
+    let cb = CodeBox::load_from_string("\"ab\"++;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.push_i64(1);
+    interpreter.profile = Some(Profile::new());
+
+    let result = interpreter.run(&cb);
+
+    assert!(result.is_ok());
+    let profile = interpreter.profile.unwrap();
+    assert_eq!(profile.steps, 7);
+    assert_eq!(profile.string_steps, 2);
+    assert_eq!(profile.instructions[&b'"'], 2);
+    assert_eq!(profile.instructions[&b'+'], 2);
+    assert_eq!(profile.cells[&(4, 0)], 1);
+}
+
+#[test]
+fn coverage_tracks_skipped_cells_and_branches() {
+    let cb = CodeBox::load_from_string("0?1!2;3");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.coverage = Some(Coverage::new());
+
+    let result = interpreter.run(&cb);
+
+    assert!(result.is_ok());
+    let coverage = interpreter.coverage.unwrap();
+    assert_eq!(coverage.cell(&cb, 0, 0), CellCoverage::Covered);
+    assert_eq!(coverage.cell(&cb, 1, 0), CellCoverage::Partial);
+    assert_eq!(coverage.cell(&cb, 2, 0), CellCoverage::Skipped);
+    assert_eq!(coverage.cell(&cb, 3, 0), CellCoverage::Covered);
+    assert_eq!(coverage.cell(&cb, 4, 0), CellCoverage::Skipped);
+    assert_eq!(coverage.cell(&cb, 5, 0), CellCoverage::Covered);
+    assert_eq!(coverage.cell(&cb, 6, 0), CellCoverage::Missed);
+}
+
+#[test]
+fn replay_reproduces_recorded_run() {
+    let code = "i:0(?;x\n  >   n\n^\n  <";
+    let cb = CodeBox::load_from_string(code);
+
+    let mut recorded = Vec::new();
+    let mut interpreter = Interpreter::new(&b"abc"[..], &mut recorded);
+    interpreter.set_seed(1);
+    interpreter.recording = Some(Recording::new(&cb, vec![]));
+    let _ = interpreter.run_for(&cb, 1000);
+    let recording = interpreter.recording.take().unwrap();
+
+    let mut replayed = Vec::new();
+    let mut interpreter = Interpreter::new(empty(), &mut replayed);
+    interpreter.set_seed(2);
+    interpreter.replay = Some(Replay::new(&recording));
+    let _ = interpreter.run_for(&recording.code_box(), 1000);
+
+    assert!(!recording.events.is_empty());
+    assert_eq!(interpreter.replay.unwrap().remaining(), 0);
+    assert_eq!(replayed, recorded);
+}
+
+type State = (
+    InstructionPtr,
+    Direction,
+    Vec<(Vec<Val>, Option<Val>)>,
+    Vec<(i64, i64, Val)>,
+    u64,
+);
+
+fn state_of<R: std::io::Read, W: std::io::Write>(interpreter: &Interpreter<R, W>) -> State {
+    let stacks = std::iter::once(&interpreter.stack.initial_stack)
+        .chain(&interpreter.stack.additional_stacks)
+        .map(|s| (Vec::from(s.values.clone()), s.register.clone()))
+        .collect();
+    let memory = interpreter
+        .written_cells()
+        .into_iter()
+        .map(|(pos, v)| (pos.x, pos.y, v))
+        .collect();
+    (
+        interpreter.ip.clone(),
+        interpreter.dir.clone(),
+        stacks,
+        memory,
+        interpreter.steps,
+    )
+}
+
+#[test]
+fn step_back_restores_previous_states() {
+    let cb = CodeBox::load_from_string("i12+:*5$@}{r&:&3[l]'ab'a01p01gx\n;");
+    let mut interpreter = Interpreter::new(&b"z"[..], sink());
+    interpreter.set_seed(3);
+    interpreter.history = Some(History::new(100));
+
+    let mut states = vec![state_of(&interpreter)];
+    for _ in 0..40 {
+        match interpreter.step(&cb) {
+            Ok(StepOutcome::Stopped { .. }) | Err(_) => break,
+            Ok(_) => states.push(state_of(&interpreter)),
+        }
+    }
+    assert!(states.len() > 25);
+
+    states.pop();
+    while interpreter.step_back() {
+        assert_eq!(state_of(&interpreter), states.pop().unwrap());
+    }
+    assert!(states.is_empty());
+}
+
+#[test]
+fn step_back_reuses_input_and_random_directions() {
+    let cb = CodeBox::load_from_string("iix");
+    let mut interpreter = Interpreter::new(&b"ab"[..], sink());
+    interpreter.history = Some(History::new(100));
+    interpreter.run_for(&cb, 3).unwrap();
+    let state = state_of(&interpreter);
+
+    for _ in 0..3 {
+        assert!(interpreter.step_back());
+    }
+    assert!(interpreter.stack.top().values.is_empty());
+    interpreter.set_seed(0);
+    interpreter.run_for(&cb, 3).unwrap();
+
+    assert_eq!(state_of(&interpreter), state);
+}
+
+#[test]
+fn step_back_reuses_input_of_failing_step() {
+    // the second byte does not continue the UTF-8 character
+    let cb = CodeBox::load_from_string("1i");
+    let mut interpreter = Interpreter::new(&b"\xc3(x"[..], sink());
+    interpreter.unicode = true;
+    interpreter.history = Some(History::new(10));
+    interpreter.step(&cb).unwrap();
+    assert_eq!(interpreter.step(&cb), Err(RuntimeError::IOError));
+
+    assert!(interpreter.step_back());
+    interpreter.step(&cb).unwrap();
+
+    assert_eq!(interpreter.step(&cb), Err(RuntimeError::IOError));
+    assert_eq!(interpreter.stack.top().values, vec![Val::Byte(1)]);
+}
+
+#[test]
+fn step_back_is_bounded_by_history_size() {
+    let cb = CodeBox::load_from_string("12345;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.history = Some(History::new(2));
+    interpreter.run_for(&cb, 5).unwrap();
+
+    assert!(interpreter.step_back());
+    assert!(interpreter.step_back());
+    assert!(!interpreter.step_back());
+    assert_eq!(interpreter.steps, 3);
+    assert_eq!(
+        interpreter.stack.top().values,
+        vec![Val::Byte(1), Val::Byte(2), Val::Byte(3)]
+    );
+}
+
+#[test]
+fn step_back_restores_fishpy_line_widths() {
+    let cb = CodeBox::load_from_string("0a0p");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.compat = Compat::FishPy;
+    interpreter.history = Some(History::new(10));
+    interpreter.run_for(&cb, 4).unwrap();
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 4, line: 0 });
+
+    // the write made the first line wider
+    assert!(interpreter.step_back());
+    interpreter.ip = InstructionPtr { chr: 0, line: 0 };
+    interpreter.dir = Direction::Left;
+    interpreter.step(&cb).unwrap();
+
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 3, line: 0 });
+}
+
+#[test]
+fn step_back_restores_output_newline() {
+    let cb = CodeBox::load_from_string("ao1n");
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(empty(), &mut output);
+    interpreter.history = Some(History::new(10));
+    interpreter.run_for(&cb, 4).unwrap();
+    assert_eq!(interpreter.output_ends_with_newline(), Some(false));
+
+    assert!(interpreter.step_back());
+    assert_eq!(interpreter.output_ends_with_newline(), Some(true));
+    assert!(interpreter.step_back());
+    assert!(interpreter.step_back());
+    assert_eq!(interpreter.output_ends_with_newline(), None);
+}
+
+#[test]
+fn failing_step_keeps_previous_state_with_history() {
+    let cb = CodeBox::load_from_string("1+");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.history = Some(History::new(10));
+
+    assert_eq!(interpreter.run(&cb), Err(RuntimeError::StackUnderflow));
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 1, line: 0 });
+    assert_eq!(interpreter.steps, 1);
+    assert_eq!(interpreter.stack.top().values, vec![Val::Byte(1)]);
+}
+
+#[test]
+fn restored_snapshot_resumes_run() {
+    let cb = CodeBox::load_from_string("xa1p\n'x'\n x3g");
+    let start = |steps| {
+        let mut interpreter = Interpreter::new(empty(), sink());
+        interpreter.set_seed(9);
+        for i in 0..100 {
+            interpreter.push_i64(i);
+        }
+        interpreter.run_for(&cb, steps).unwrap();
+        interpreter
+    };
+
+    let expected = start(300).snapshot(&cb);
+
+    let saved = serde_json::to_string(&start(150).snapshot(&cb)).unwrap();
+    let snapshot: Snapshot = serde_json::from_str(&saved).unwrap();
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.restore(&snapshot);
+    interpreter.run_for(&snapshot.code_box(), 150).unwrap();
+
+    assert_eq!(interpreter.steps, 300);
+    assert!(!interpreter.memory.is_empty());
+    assert_eq!(
+        serde_json::to_value(interpreter.snapshot(&cb)).unwrap(),
+        serde_json::to_value(expected).unwrap()
+    );
+}
+
+#[test]
+fn restored_snapshot_keeps_fishpy_line_widths() {
+    // p makes the first line wider, so that 1n runs before wrapping
+    let cb = CodeBox::load_from_string("\"n;\"a0p1n");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.compat = Compat::FishPy;
+    interpreter.run_for(&cb, 7).unwrap();
+    let saved = serde_json::to_string(&interpreter.snapshot(&cb)).unwrap();
+
+    let snapshot: Snapshot = serde_json::from_str(&saved).unwrap();
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(empty(), &mut output);
+    interpreter.restore(&snapshot);
+
+    assert_eq!(interpreter.compat, Compat::FishPy);
+    assert_eq!(interpreter.resume(&snapshot.code_box()), Ok(()));
+    drop(interpreter);
+    assert_eq!(output, b"1");
+}
+
+/// Runs code with an interpreter set up by `configure`, returns the result and the output.
+fn run_with<F>(code: &str, input: &str, configure: F) -> (Result<()>, String)
+where
+    F: FnOnce(&mut CodeBox, &mut Interpreter<&[u8], &mut Vec<u8>>),
+{
+    let mut cb = CodeBox::load_from_string(code);
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(input.as_bytes(), &mut output);
+    configure(&mut cb, &mut interpreter);
+    let result = interpreter.run(&cb);
+    (result, String::from_utf8(output).unwrap())
+}
+
+fn fishpy(cb: &mut CodeBox, interpreter: &mut Interpreter<&[u8], &mut Vec<u8>>) {
+    cb.strip_hashbang();
+    interpreter.compat = Compat::FishPy;
+}
+
+#[test]
+fn fishpy_compat_compares_real_values() {
+    assert_eq!(run_with("52,2=n;", "", fishpy), (Ok(()), "0".to_string()));
+    assert_eq!(run_with("52,2)n;", "", fishpy), (Ok(()), "1".to_string()));
+    assert_eq!(run_with("12,?1n;", "", fishpy), (Ok(()), "1".to_string()));
+    assert_eq!(
+        run_with("54,2%n;", "", fishpy),
+        (Ok(()), "1.25".to_string())
+    );
+}
+
+#[test]
+fn fishpy_compat_wraps_per_line() {
+    let cb = CodeBox::load_from_string("v    \n>1n");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.compat = Compat::FishPy;
+
+    assert_eq!(interpreter.run_for(&cb, 4), Ok(RunState::Suspended));
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 0, line: 1 });
+
+    // reading a cell with g adds lines up to it
+    let cb = CodeBox::load_from_string("05g~1^\n     ;");
+    interpreter.reset();
+    assert_eq!(interpreter.run(&cb), Ok(()));
+    assert_eq!(interpreter.steps, 11);
+}
+
+#[test]
+fn fishpy_compat_strips_hashbang() {
+    assert_eq!(
+        run_with("#!/usr/bin/env fish\n1n;", "", fishpy),
+        (Ok(()), "1".to_string())
+    );
+}
+
+#[test]
+fn fishpy_compat_handles_stacks_and_characters() {
+    assert_eq!(
+        run_with("}", "", fishpy).0,
+        Err(RuntimeError::StackUnderflow)
+    );
+    assert_eq!(run_with("12 5[ln;", "", fishpy), (Ok(()), "2".to_string()));
+    assert_eq!(run_with("ioin;", "é", fishpy), (Ok(()), "é-1".to_string()));
+    assert_eq!(
+        run_with("01-o;", "", fishpy).0,
+        Err(RuntimeError::InvalidCharacter)
+    );
+}
+
+#[test]
+fn advance_on_empty_code_does_not_panic() {
+    let cb = CodeBox::load_from_string("");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    for dir in [Direction::Left, Direction::Up] {
+        interpreter.dir = dir;
+        interpreter.advance(&cb);
+        assert_eq!(interpreter.ip, InstructionPtr { chr: 0, line: 0 });
+    }
+}
+
+#[test]
+fn negative_new_stack_size_fails() {
+    let cb = CodeBox::load_from_string("01-[;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+
+    assert_eq!(interpreter.run(&cb), Err(RuntimeError::StackUnderflow));
+}
+
+#[test]
+fn fishpy_compat_writes_far_away_cells() {
+    let cb = CodeBox::load_from_string("10ff*:*:*:*p;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.compat = Compat::FishPy;
+
+    assert_eq!(interpreter.run(&cb), Ok(()));
+}
+
+fn unicode(cb: &mut CodeBox, interpreter: &mut Interpreter<&[u8], &mut Vec<u8>>) {
+    cb.decode_utf8();
+    interpreter.unicode = true;
+}
+
+#[test]
+fn unicode_strings_push_code_points() {
+    let mut cb = CodeBox::load_from_string("\"héllo☺\";");
+    cb.decode_utf8();
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.unicode = true;
+
+    assert_eq!(interpreter.run(&cb), Ok(()));
+    assert_eq!(
+        interpreter.stack.top().values,
+        vec![
+            Val::Int(104),
+            Val::Int(233),
+            Val::Int(108),
+            Val::Int(108),
+            Val::Int(111),
+            Val::Int(0x263a)
+        ]
+    );
+    assert_eq!(
+        run_with("\"☺éh\"ooo;", "", unicode),
+        (Ok(()), "hé☺".to_string())
+    );
+}
+
+#[test]
+fn unicode_input_and_cells_use_code_points() {
+    assert_eq!(
+        run_with("iiinoo;", "☺a", unicode),
+        (Ok(()), "-1a☺".to_string())
+    );
+    assert_eq!(
+        run_with("50gn;☺", "", unicode),
+        (Ok(()), "9786".to_string())
+    );
+    // a character written with p is pushed whole in string mode
+    assert_eq!(
+        run_with("\"☺\"80p'  'o;", "", unicode),
+        (Ok(()), "☺".to_string())
+    );
+    assert_eq!(
+        run_with("1b-o;", "", unicode).0,
+        Err(RuntimeError::InvalidCharacter)
+    );
+}
+
+fn growing(_: &mut CodeBox, interpreter: &mut Interpreter<&[u8], &mut Vec<u8>>) {
+    interpreter.grow_code = true;
+    interpreter.limits.max_steps = Some(1000);
+}
+
+#[test]
+fn growing_code_reaches_written_cells() {
+    // n and ; are written right of the code
+    assert_eq!(
+        run_with("1'n'e0p';'f0p", "", growing),
+        (Ok(()), "1".to_string())
+    );
+    // and above it, at negative coordinates
+    assert_eq!(
+        run_with(" 1'n'001-p';'002-pv\n^                 <", "", growing),
+        (Ok(()), "1".to_string())
+    );
+    // wrapping goes around all of the written cells
+    assert_eq!(
+        run_with("1'n'02-0p';'01-0p", "", growing),
+        (Ok(()), "1".to_string())
+    );
+}
+
+#[test]
+fn growing_code_includes_cells_written_with_zero() {
+    // a cell left of the code, written with the value read there
+    let cb = CodeBox::load_from_string("001-0p;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.grow_code = true;
+
+    assert_eq!(interpreter.run(&cb), Ok(()));
+    assert!(interpreter.cell_at(&cb, -1, 0).is_some());
+    assert_eq!(interpreter.cell_at(&cb, -2, 0), None);
+}
+
+#[test]
+fn growing_code_is_restored_by_step_back() {
+    let cb = CodeBox::load_from_string("'n'01-0p");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.grow_code = true;
+    interpreter.history = Some(History::new(10));
+
+    assert_eq!(interpreter.run_for(&cb, 8), Ok(RunState::Suspended));
+    assert_eq!(interpreter.ip, InstructionPtr { chr: -1, line: 0 });
+    assert!(interpreter.step_back());
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 7, line: 0 });
+    interpreter.dir = Direction::Right;
+    interpreter.advance(&cb);
+    assert_eq!(interpreter.ip, InstructionPtr { chr: 0, line: 0 });
+}
+
+#[test]
+fn growing_code_far_away_is_rendered_with_elisions() {
+    // writes a cell about 6.5e18 columns right of the code
+    let cb = CodeBox::load_from_string("1ff*:*:*:*0p;");
+    let mut interpreter = Interpreter::new(empty(), sink());
+    interpreter.grow_code = true;
+    interpreter.profile = Some(Profile::new());
+    interpreter.coverage = Some(Coverage::new());
+
+    assert_eq!(interpreter.run(&cb), Ok(()));
+    let view = interpreter.view(&cb);
+    assert_eq!(view.lines(), vec![Some(0)]);
+    let columns = view.columns();
+    assert_eq!(columns.len(), 15);
+    assert_eq!(columns[13], None);
+    assert_eq!(columns[14], Some(6_568_408_355_712_890_625));
+
+    let heatmap = interpreter.profile.unwrap().heatmap(&cb);
+    assert!(heatmap.len() < 100);
+    let coverage = interpreter.coverage.unwrap();
+    assert!(coverage.annotate(&cb).len() < 200);
+    assert_eq!(coverage.to_json(&cb)["cells"].as_array().unwrap().len(), 13);
+}
@@ -113,3 +113,105 @@ fn mod_negative_value_works() {
     assert!(result.is_ok());
     assert_eq!(interpreter.stack.top().values, vec![Val::Int(12)]);
 }
+
+#[cfg(feature = "bignum")]
+#[test]
+fn factorial_does_not_overflow_with_bignum() {
+    let cb = CodeBox::load_from_file("examples/factorial.fish").unwrap();
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(empty(), &mut output);
+    interpreter.push_i64(25);
+
+    let result = interpreter.run(&cb);
+
+    assert!(result.is_ok());
+    assert_eq!(output, b"15511210043330985984000000");
+}
+
+#[cfg(feature = "bignum")]
+#[test]
+fn bignum_comparisons_and_modulo_work() {
+    // each program starts by computing 2^64
+    let run = |code: &str| {
+        let cb = CodeBox::load_from_string(&format!("2:*:*:*:*:*:*{};", code));
+        let mut interpreter = Interpreter::new(empty(), sink());
+        assert!(interpreter.run(&cb).is_ok());
+        interpreter.stack.top().values.clone()
+    };
+
+    assert_eq!(run(":1-)"), vec![Val::Byte(1)]);
+    assert_eq!(run(":1-("), vec![Val::Byte(0)]);
+    assert_eq!(run(":1-="), vec![Val::Byte(0)]);
+    assert_eq!(run(":="), vec![Val::Byte(1)]);
+    assert_eq!(run("a%"), vec![Val::Int(6)]);
+}
+
+#[test]
+fn overflow_policy_is_honored() {
+    // i64::MAX + 1
+    let cb = CodeBox::load_from_string("1+;");
+    let run = |policy| {
+        let mut interpreter = Interpreter::new(empty(), sink());
+        interpreter.overflow = policy;
+        interpreter.push_i64(i64::MAX);
+        interpreter
+            .run(&cb)
+            .map(|_| Vec::from(interpreter.stack.top().values.clone()))
+    };
+
+    assert_eq!(
+        run(OverflowPolicy::Error),
+        Err(RuntimeError::IntegerOverflow)
+    );
+    assert_eq!(run(OverflowPolicy::Wrap), Ok(vec![Val::Int(i64::MIN)]));
+    assert_eq!(run(OverflowPolicy::Saturate), Ok(vec![Val::Int(i64::MAX)]));
+    assert_eq!(
+        run(OverflowPolicy::Float),
+        Ok(vec![Val::Float(9223372036854775808.0)])
+    );
+}
+
+#[cfg(feature = "rational")]
+#[test]
+fn exact_division_yields_fractions() {
+    let cb = CodeBox::load_from_string("13,:3*$0)1a,3*1a,7*+n;");
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new(empty(), &mut output);
+    interpreter.exact_division = true;
+
+    let result = interpreter.run(&cb);
+
+    assert!(result.is_ok());
+    assert_eq!(
+        interpreter.stack.top().values,
+        vec![Val::Int(1), Val::Byte(1)]
+    );
+    assert_eq!(output, b"1");
+}
+
+#[test]
+fn python_number_format_is_honored() {
+    let cb = CodeBox::load_from_string("1aa*:*a*,n\" \"o92,n\" \"o62,n;");
+    let run = |format| {
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::new(empty(), &mut output);
+        interpreter.number_format = format;
+        assert!(interpreter.run(&cb).is_ok());
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(run(NumberFormat::Native), "0.00001 4.5 3");
+    assert_eq!(run(NumberFormat::Python), "1e-05 4.5 3");
+}
+
+#[test]
+fn python_number_format_fails_on_non_finite_floats() {
+    let cb = CodeBox::load_from_string("n;");
+    for f in [f64::INFINITY, f64::NAN] {
+        let mut interpreter = Interpreter::new(empty(), sink());
+        interpreter.number_format = NumberFormat::Python;
+        interpreter.stack.top_mut().push(Val::Float(f));
+
+        assert_eq!(interpreter.run(&cb), Err(RuntimeError::NonFiniteFloat));
+    }
+}